@@ -5,4 +5,6 @@ pub type Channel = u8;
 
 pub mod channel;
 pub mod deck;
+pub mod key;
 pub mod master_clock;
+pub mod stats;
@@ -1,3 +1,10 @@
+use super::{
+    key::{KeyNotation, MusicalKey},
+    master_clock::{DeckTempo, MasterClock},
+    stats::TrackStats,
+};
+use std::collections::BTreeMap;
+
 /// Describes a deck status
 #[derive(Deserialize, Clone, Debug, Default, Serialize)]
 #[serde(rename_all = "camelCase", default)]
@@ -50,18 +57,131 @@ pub struct DeckStatus {
     /// Deck letter the track is playing on
     #[serde(default)]
     pub deck: Option<String>,
+    /// Whether the relay shows the track as an ID, hiding its metadata
+    #[serde(skip_deserializing)]
+    pub is_hidden: bool,
+    /// Featured artists taken out of the title or artist by the cleanup rules
+    #[serde(skip_deserializing)]
+    pub featuring: String,
+    /// Values of the fields before the cleanup rules changed them
+    #[serde(skip_deserializing, skip_serializing_if = "BTreeMap::is_empty")]
+    pub original: BTreeMap<TrackField, String>,
+    /// `key` in every notation, if it could be read
+    #[serde(skip_deserializing)]
+    pub key_notation: Option<KeyNotation>,
+    /// `resulting_key` in every notation, if it could be read
+    #[serde(skip_deserializing)]
+    pub resulting_key_notation: Option<KeyNotation>,
+    /// BPM with the tempo applied
+    #[serde(skip_deserializing)]
+    pub effective_bpm: f32,
+    /// Whether the deck is the tempo master
+    #[serde(skip_deserializing)]
+    pub is_master: bool,
+    /// Whether the effective BPM matches the master clock within the tolerance
+    #[serde(skip_deserializing)]
+    pub matches_master: bool,
+    /// Plays of the track file on air, `None` if it was never played
+    #[serde(skip_deserializing)]
+    pub stats: Option<TrackStats>,
+}
+
+/// A text metadata field of a track
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum TrackField {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Comment,
+    Comment2,
+    Label,
+    Mix,
+    Remixer,
+}
+
+impl TrackField {
+    pub const ALL: [TrackField; 9] = [
+        TrackField::Title,
+        TrackField::Artist,
+        TrackField::Album,
+        TrackField::Genre,
+        TrackField::Comment,
+        TrackField::Comment2,
+        TrackField::Label,
+        TrackField::Mix,
+        TrackField::Remixer,
+    ];
 }
 
 impl DeckStatus {
-    /// Update the status entry from a delta object, returns whether the change affects the Now Playing status
-    pub fn update(&mut self, delta: DeckStatusUpdate) -> bool {
-        use crate::settings::ServerSettings;
+    /// Key of the track, from `key` or else from `key_text`
+    pub fn musical_key(&self) -> Option<MusicalKey> {
+        MusicalKey::parse(&self.key).or_else(|| MusicalKey::parse(&self.key_text))
+    }
+
+    /// Key of the track after the key adjustment, the track key if no adjustment was reported
+    pub fn resulting_musical_key(&self) -> Option<MusicalKey> {
+        MusicalKey::parse(&self.resulting_key).or_else(|| self.musical_key())
+    }
+
+    /// BPM the track is playing at, with the tempo applied
+    pub fn effective_bpm(&self) -> f32 {
+        self.bpm * self.tempo
+    }
+
+    /// Tempo of the deck against the master clock
+    pub fn tempo(&self, deck: &str, clock: &MasterClock, tolerance: f32) -> DeckTempo {
+        let effective_bpm = self.effective_bpm();
+        DeckTempo {
+            deck: deck.to_string(),
+            effective_bpm,
+            is_master: clock.is_master(deck),
+            is_synced: self.is_synced,
+            matches_master: clock.matches(effective_bpm, tolerance),
+        }
+    }
+
+    /// Fill in the tempo fields from the master clock
+    pub fn update_tempo(&mut self, clock: &MasterClock, tolerance: f32) {
+        if let Some(deck) = self.deck.clone() {
+            let tempo = self.tempo(&deck, clock, tolerance);
+            self.effective_bpm = tempo.effective_bpm;
+            self.is_master = tempo.is_master;
+            self.matches_master = tempo.matches_master;
+        }
+    }
+
+    /// Fill in the key notations from the keys Traktor has sent
+    pub fn update_key_notation(&mut self) {
+        self.key_notation = self.musical_key().map(|key| key.notation());
+        self.resulting_key_notation = self.resulting_musical_key().map(|key| key.notation());
+    }
+
+    /// Get a text metadata field for changing
+    pub fn field_mut(&mut self, field: TrackField) -> &mut String {
+        match field {
+            TrackField::Title => &mut self.title,
+            TrackField::Artist => &mut self.artist,
+            TrackField::Album => &mut self.album,
+            TrackField::Genre => &mut self.genre,
+            TrackField::Comment => &mut self.comment,
+            TrackField::Comment2 => &mut self.comment2,
+            TrackField::Label => &mut self.label,
+            TrackField::Mix => &mut self.mix,
+            TrackField::Remixer => &mut self.remixer,
+        }
+    }
 
+    /// Update the status entry from a delta object, returns whether the change affects the Now Playing status.
+    /// Minor changes such as elapsed time only count when `more_events` is set.
+    pub fn update(&mut self, delta: DeckStatusUpdate, more_events: bool) -> bool {
         trace!("Updating deck {:?} with delta: {:?}", self.deck, delta);
         let mut rslt = false;
         if let Some(time) = delta.elapsed_time {
             self.elapsed_time = time;
-            rslt |= ServerSettings::shared().http.more_events;
+            rslt |= more_events;
         }
         if let Some(playing) = delta.is_playing {
             self.is_playing = playing;
@@ -75,10 +195,11 @@ impl DeckStatus {
         }
         if let Some(tempo) = delta.tempo {
             self.tempo = tempo;
-            rslt |= ServerSettings::shared().http.more_events;
+            rslt |= more_events;
         }
         if let Some(res_key) = delta.resulting_key {
             self.resulting_key = res_key;
+            self.update_key_notation();
         }
         rslt
     }
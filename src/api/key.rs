@@ -0,0 +1,158 @@
+/// Note names by pitch class, sharp or flat as they are usually written in each key
+const MAJOR_NAMES: [&str; 12] = ["C", "Db", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B"];
+const MINOR_NAMES: [&str; 12] = ["C", "C#", "D", "Eb", "E", "F", "F#", "G", "G#", "A", "Bb", "B"];
+
+/// How well two keys mix, by their places on the Camelot wheel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum KeyRelation {
+    /// The same key
+    Same,
+    /// Relative major or minor: same number, other letter
+    Relative,
+    /// One step around the wheel in the same mode
+    Adjacent,
+    /// Anything else
+    Clash,
+}
+
+/// A musical key: tonic and mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MusicalKey {
+    /// Pitch class of the tonic, 0 for C to 11 for B
+    pub tonic: u8,
+    /// Minor or major mode
+    pub minor: bool,
+}
+
+/// A key written in every notation, as sent to the clients
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyNotation {
+    /// Camelot wheel, e.g. "8A"
+    pub camelot: String,
+    /// Open Key, also used by Traktor, e.g. "1m"
+    pub open_key: String,
+    /// Short musical notation, e.g. "Am"
+    pub musical: String,
+    /// Full musical name, e.g. "A minor"
+    pub name: String,
+}
+
+impl MusicalKey {
+    /// Read a key in Open Key (Traktor), Camelot or musical notation
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let digits = text.chars().take_while(char::is_ascii_digit).count();
+        if digits > 0 {
+            let number: u8 = text[..digits].parse().ok()?;
+            if !(1..=12).contains(&number) {
+                return None;
+            }
+            return match &text[digits..] {
+                "m" | "M" => Some(Self::from_open_key(number, true)),
+                "d" | "D" => Some(Self::from_open_key(number, false)),
+                "A" | "a" => Some(Self::from_camelot(number, true)),
+                "B" | "b" => Some(Self::from_camelot(number, false)),
+                _ => None,
+            };
+        }
+        Self::parse_musical(text)
+    }
+
+    /// Read a key like "Am", "F#", "Eb minor" or "C major"
+    fn parse_musical(text: &str) -> Option<Self> {
+        let mut chars = text.chars();
+        let mut tonic: i8 = match chars.next()?.to_ascii_uppercase() {
+            'C' => 0,
+            'D' => 2,
+            'E' => 4,
+            'F' => 5,
+            'G' => 7,
+            'A' => 9,
+            'B' => 11,
+            _ => return None,
+        };
+        let mut rest = chars.as_str();
+        if let Some(c) = rest.chars().next() {
+            let accidental = match c {
+                '#' | '♯' => 1,
+                'b' | '♭' => -1,
+                _ => 0,
+            };
+            if accidental != 0 {
+                tonic += accidental;
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+        let minor = match rest.trim().to_lowercase().as_str() {
+            "" | "maj" | "major" => false,
+            "m" | "min" | "minor" => true,
+            _ => return None,
+        };
+        Some(Self { tonic: tonic.rem_euclid(12) as u8, minor })
+    }
+
+    fn from_open_key(number: u8, minor: bool) -> Self {
+        // 1d is C major, every next number is a fifth up; minor keys share the number with their relative major
+        let major_tonic = ((number - 1) * 7) % 12;
+        Self::from_relative_major(major_tonic, minor)
+    }
+
+    fn from_camelot(number: u8, minor: bool) -> Self {
+        // Camelot wheel is the Open Key wheel turned by seven steps: 8B is C major
+        Self::from_open_key((number + 4) % 12 + 1, minor)
+    }
+
+    fn from_relative_major(major_tonic: u8, minor: bool) -> Self {
+        let tonic = if minor { (major_tonic + 9) % 12 } else { major_tonic };
+        Self { tonic, minor }
+    }
+
+    /// Pitch class of the major key with the same notes
+    fn relative_major_tonic(&self) -> u8 {
+        if self.minor {
+            (self.tonic + 3) % 12
+        } else {
+            self.tonic
+        }
+    }
+
+    /// Number of the key on the Open Key wheel, 1 to 12
+    pub fn open_key_number(&self) -> u8 {
+        // Multiplying by 7 (its own inverse mod 12) turns semitones into steps around the circle of fifths
+        (self.relative_major_tonic() * 7) % 12 + 1
+    }
+
+    /// Number of the key on the Camelot wheel, 1 to 12
+    pub fn camelot_number(&self) -> u8 {
+        (self.open_key_number() + 6) % 12 + 1
+    }
+
+    /// How well the key mixes with the other one
+    pub fn relation(&self, other: &MusicalKey) -> KeyRelation {
+        let steps = (self.camelot_number() + 12 - other.camelot_number()) % 12;
+        match (steps, self.minor == other.minor) {
+            (0, true) => KeyRelation::Same,
+            (0, false) => KeyRelation::Relative,
+            (1, true) | (11, true) => KeyRelation::Adjacent,
+            _ => KeyRelation::Clash,
+        }
+    }
+
+    /// The key written in every notation
+    pub fn notation(&self) -> KeyNotation {
+        let (names, mode, suffix) = if self.minor {
+            (&MINOR_NAMES, "minor", "m")
+        } else {
+            (&MAJOR_NAMES, "major", "")
+        };
+        let note = names[self.tonic as usize];
+        KeyNotation {
+            camelot: format!("{}{}", self.camelot_number(), if self.minor { "A" } else { "B" }),
+            open_key: format!("{}{}", self.open_key_number(), if self.minor { "m" } else { "d" }),
+            musical: format!("{}{}", note, suffix),
+            name: format!("{} {}", note, mode),
+        }
+    }
+}
@@ -9,3 +9,31 @@ pub struct MasterClock {
     /// Current BPM
     pub bpm: f32,
 }
+
+impl MasterClock {
+    /// Whether the deck is the tempo master
+    pub fn is_master(&self, deck: &str) -> bool {
+        self.deck.as_deref() == Some(deck)
+    }
+
+    /// Whether the BPM is within `tolerance` of the master clock
+    pub fn matches(&self, bpm: f32, tolerance: f32) -> bool {
+        self.bpm > 0.0 && (bpm - self.bpm).abs() <= tolerance
+    }
+}
+
+/// Tempo of a deck against the master clock
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeckTempo {
+    /// Deck letter
+    pub deck: Deck,
+    /// BPM with the tempo applied
+    pub effective_bpm: f32,
+    /// Whether the deck is the tempo master
+    pub is_master: bool,
+    /// Whether the deck is synced in Traktor
+    pub is_synced: bool,
+    /// Whether the effective BPM matches the master clock within the tolerance
+    pub matches_master: bool,
+}
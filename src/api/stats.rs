@@ -0,0 +1,15 @@
+/// What the relay remembers about the plays of a track
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TrackStats {
+    /// Times the track started playing on air
+    pub play_count: u32,
+    /// Seconds the track was playing on air in total
+    pub airtime: f64,
+    /// When the track last started playing on air, in seconds since the Unix epoch
+    pub last_played: Option<f64>,
+    /// Artist of the track when it was last played
+    pub artist: String,
+    /// Title of the track when it was last played
+    pub title: String,
+}
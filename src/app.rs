@@ -0,0 +1,261 @@
+use super::{
+    error::Result,
+    http_server,
+    logic::ArtworkCache,
+    metrics::Metrics,
+    obs::{self, ObsClient},
+    outputs::{self, FileOutputs},
+    overrides,
+    recorder::Recorder,
+    settings::{ReloadReport, ServerSettings, SettingsError, SettingsSource},
+    state::RelayState,
+    stats,
+    status::{self, Ingest},
+    streams::{self, StreamMetadata},
+    throttle::{self, BpmThrottle},
+    ws_server,
+};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// How long to wait for the servers to finish the requests in flight on shutdown
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A relay instance: its settings and everything it knows about the mix.
+///
+/// The mix is kept in a single `RelayState` lock. Handlers take it once, build whatever they
+/// are going to send while holding it, and release it before pushing to the websocket clients.
+/// The other locks are never held while acquiring the state.
+pub struct App {
+    /// Where the settings were read from
+    source: SettingsSource,
+    /// Active settings
+    settings: RwLock<Arc<ServerSettings>>,
+    /// What Traktor has told the relay
+    state: RwLock<RelayState>,
+    /// Connected websocket clients
+    pub(crate) subscribers: ws_server::PeerMap,
+    /// Counters for the metrics endpoint
+    pub(crate) metrics: Metrics,
+    /// Artwork of the loaded tracks
+    pub(crate) artwork: ArtworkCache,
+    /// Master clock changes pushed to the websocket clients
+    pub(crate) bpm_throttle: BpmThrottle,
+    /// Files written for the OBS sources
+    pub(crate) outputs: FileOutputs,
+    /// Connection to OBS
+    pub(crate) obs: ObsClient,
+    /// Song titles of the streams
+    pub(crate) streams: StreamMetadata,
+    /// Session recording, if one was requested
+    recorder: Mutex<Option<Recorder>>,
+    /// When the instance was created
+    started_at: Instant,
+    /// Set once the instance is shutting down
+    shutdown: watch::Sender<bool>,
+    /// Server tasks to wait for on shutdown
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+/// Addresses the servers of a started relay are listening at
+#[derive(Debug, Clone, Copy)]
+pub struct Endpoints {
+    pub http: SocketAddr,
+    pub ws: SocketAddr,
+}
+
+impl App {
+    /// Create a relay instance with the settings read from `source`
+    pub fn new(settings: ServerSettings, source: SettingsSource) -> Arc<Self> {
+        let mut state = RelayState::new(&settings.mixing);
+        match overrides::load(&settings.mixing.overrides_file) {
+            Ok(file_overrides) => state.file_overrides = file_overrides,
+            Err(e) => error!("Could not read the overrides from {}: {}", settings.mixing.overrides_file, e),
+        }
+        match stats::load(&settings.mixing.stats_file) {
+            Ok(track_stats) => state.track_stats = track_stats,
+            Err(e) => error!("Could not read the statistics from {}: {}", settings.mixing.stats_file, e),
+        }
+        Arc::new(Self {
+            source,
+            state: RwLock::new(state),
+            settings: RwLock::new(Arc::new(settings)),
+            subscribers: ws_server::PeerMap::default(),
+            metrics: Metrics::default(),
+            artwork: ArtworkCache::default(),
+            bpm_throttle: BpmThrottle::default(),
+            outputs: FileOutputs::default(),
+            obs: ObsClient::default(),
+            streams: StreamMetadata::default(),
+            recorder: Mutex::new(None),
+            started_at: Instant::now(),
+            shutdown: watch::channel(false).0,
+            tasks: Mutex::new(vec![]),
+        })
+    }
+
+    /// Start the HTTP and websocket servers, and the tasks pushing the throttled master clock,
+    /// writing the output files, driving OBS and updating the stream song titles, on the current
+    /// tokio runtime
+    pub fn start(self: &Arc<Self>) -> Result<Endpoints> {
+        let (http, http_task) = http_server::start(self.clone())?;
+        let (ws, ws_task) = ws_server::start(self.clone())?;
+        self.spawn(http_task);
+        self.spawn(ws_task);
+        self.spawn(status::spawn_watchdog(self.clone()));
+        self.spawn(throttle::spawn(self.clone()));
+        self.spawn(outputs::spawn(self.clone()));
+        self.spawn(obs::spawn(self.clone()));
+        self.spawn(streams::spawn(self.clone()));
+        self.spawn(stats::spawn_saver(self.clone()));
+        Ok(Endpoints { http, ws })
+    }
+
+    /// Keep track of a task that has to finish before the shutdown is complete
+    pub fn spawn(&self, task: JoinHandle<()>) {
+        match self.tasks.lock() {
+            Ok(mut tasks) => tasks.push(task),
+            Err(e) => e.into_inner().push(task),
+        }
+    }
+
+    /// Stop serving: close the websocket connections, let the servers finish the requests in flight
+    /// and flush the session recording
+    pub async fn shutdown(&self) {
+        info!("Shutting down");
+        self.shutdown.send_replace(true);
+        ws_server::close_all(self).await;
+
+        let tasks = match self.tasks.lock() {
+            Ok(mut tasks) => std::mem::take(&mut *tasks),
+            Err(e) => std::mem::take(&mut *e.into_inner()),
+        };
+        let finished = tokio::time::timeout(SHUTDOWN_TIMEOUT, futures_util::future::join_all(tasks)).await;
+        if finished.is_err() {
+            warn!("Servers did not stop in {:?}", SHUTDOWN_TIMEOUT);
+        }
+
+        self.stop_recording();
+        self.save_stats();
+        info!("Shutdown complete");
+    }
+
+    /// Resolves once the instance starts shutting down
+    pub async fn shutdown_requested(&self) {
+        let mut shutdown = self.shutdown.subscribe();
+        while !*shutdown.borrow_and_update() {
+            if shutdown.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Get the active settings
+    pub fn settings(&self) -> Arc<ServerSettings> {
+        match self.settings.read() {
+            Ok(settings) => settings.clone(),
+            Err(e) => e.into_inner().clone(),
+        }
+    }
+
+    /// Where the settings were read from
+    pub fn settings_source(&self) -> &SettingsSource {
+        &self.source
+    }
+
+    /// Re-read the settings and swap them in place of the active settings.
+    /// Settings that cannot change while running are kept at their old values and reported.
+    pub fn reload_settings(&self) -> std::result::Result<ReloadReport, SettingsError> {
+        let mut new_settings = ServerSettings::read_validated(&self.source)?;
+        let old_settings = self.settings();
+        let report = new_settings.keep_restart_only(&old_settings);
+        log::set_max_level(new_settings.log_level_filter());
+        self.obs.settings_changed(&old_settings.obs, &new_settings.obs);
+        match self.settings.write() {
+            Ok(mut settings) => *settings = Arc::new(new_settings),
+            Err(e) => *e.into_inner() = Arc::new(new_settings),
+        }
+        info!("Settings reloaded");
+        Ok(report)
+    }
+
+    /// Lock the state for reading
+    pub fn state(&self) -> Result<RwLockReadGuard<'_, RelayState>> {
+        Ok(self.state.read()?)
+    }
+
+    /// Lock the state for changing
+    pub fn state_mut(&self) -> Result<RwLockWriteGuard<'_, RelayState>> {
+        Ok(self.state.write()?)
+    }
+
+    /// Time since the instance was created
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// Write the track statistics to the statistics file
+    pub fn save_stats(&self) {
+        let track_stats = match self.state() {
+            Ok(state) => state.track_stats.clone(),
+            Err(e) => {
+                error!("Could not save the statistics: {}", e);
+                return;
+            }
+        };
+        let path = &self.settings().mixing.stats_file;
+        if let Err(e) = stats::save(path, &track_stats) {
+            error!("Could not save the statistics to {}: {}", path, e);
+        }
+    }
+
+    /// Write the track statistics to the statistics file if they have changed since the last time
+    pub fn save_changed_stats(&self) {
+        let changed = match self.state_mut() {
+            Ok(mut state) => state.take_stats_changed(),
+            Err(e) => {
+                error!("Could not save the statistics: {}", e);
+                return;
+            }
+        };
+        if changed {
+            self.save_stats();
+        }
+    }
+
+    /// Start writing every call made by Traktor to the file at `path`
+    pub fn start_recording(&self, path: &str) -> Result<()> {
+        let recorder = Recorder::create(path)?;
+        *self.recorder.lock()? = Some(recorder);
+        Ok(())
+    }
+
+    /// Flush and close the session recording, if one is active
+    pub fn stop_recording(&self) {
+        let recorder = match self.recorder.lock() {
+            Ok(mut recorder) => recorder.take(),
+            Err(e) => e.into_inner().take(),
+        };
+        if let Some(mut recorder) = recorder {
+            match recorder.flush() {
+                Ok(()) => info!("Session recording saved"),
+                Err(e) => error!("Could not save the session recording: {}", e),
+            }
+        }
+    }
+
+    /// Write the call to the session recording, if one is active
+    pub(crate) fn record(&self, kind: Ingest, id: Option<&str>, body: &serde_json::Value) {
+        match self.recorder.lock() {
+            Ok(mut recorder) => {
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.record(kind, id, body);
+                }
+            }
+            Err(e) => error!("Recorder is unavailable: {}", e),
+        }
+    }
+}
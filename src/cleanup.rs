@@ -0,0 +1,207 @@
+use super::api::deck::{DeckStatus, TrackField};
+use regex::Regex;
+use std::convert::TryFrom;
+use std::sync::OnceLock;
+
+/// A regular expression in the settings
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Pattern(Regex);
+
+impl TryFrom<String> for Pattern {
+    type Error = regex::Error;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        Regex::new(&text).map(Pattern)
+    }
+}
+
+impl From<Pattern> for String {
+    fn from(pattern: Pattern) -> Self {
+        pattern.0.as_str().to_string()
+    }
+}
+
+/// Letter case to bring a field to
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TextCase {
+    /// Every Word Capitalized
+    Title,
+    Upper,
+    Lower,
+}
+
+/// A step of the metadata cleanup, run on every loaded track
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "rule", rename_all = "camelCase")]
+pub enum CleanupRule {
+    /// Replace the matches of `pattern` with `with`, which can refer to the groups as `$1`
+    Replace {
+        #[serde(default = "default_fields")]
+        fields: Vec<TrackField>,
+        pattern: Pattern,
+        #[serde(default)]
+        with: String,
+    },
+    /// Split an `Artist - Title` title into the artist and the title, if the artist is empty
+    SplitArtist {
+        #[serde(default = "default_separator")]
+        separator: String,
+    },
+    /// Remove any of the suffixes from the end, ignoring case
+    StripSuffix {
+        #[serde(default = "default_fields")]
+        fields: Vec<TrackField>,
+        suffixes: Vec<String>,
+    },
+    /// Change the letter case
+    Case {
+        #[serde(default = "default_fields")]
+        fields: Vec<TrackField>,
+        case: TextCase,
+    },
+    /// Move `feat. Someone` into the `featuring` field
+    ExtractFeat {
+        #[serde(default = "default_fields")]
+        fields: Vec<TrackField>,
+    },
+}
+
+fn default_fields() -> Vec<TrackField> {
+    vec![TrackField::Title, TrackField::Artist]
+}
+
+fn default_separator() -> String {
+    String::from(" - ")
+}
+
+impl CleanupRule {
+    /// Problems that make the rule useless
+    pub fn validate(&self) -> Option<String> {
+        match self {
+            CleanupRule::SplitArtist { separator } if separator.is_empty() => Some(String::from("separator is empty")),
+            CleanupRule::StripSuffix { suffixes, .. } if suffixes.iter().any(String::is_empty) => {
+                Some(String::from("suffixes contain an empty suffix"))
+            }
+            CleanupRule::Replace { fields, .. }
+            | CleanupRule::StripSuffix { fields, .. }
+            | CleanupRule::Case { fields, .. }
+            | CleanupRule::ExtractFeat { fields } if fields.is_empty() => Some(String::from("fields are empty")),
+            _ => None,
+        }
+    }
+
+    fn apply(&self, track: &mut DeckStatus) {
+        match self {
+            CleanupRule::Replace { fields, pattern, with } => {
+                for field in fields {
+                    let text = track.field_mut(*field);
+                    let replaced = pattern.0.replace_all(text, with.as_str()).trim().to_string();
+                    *text = replaced;
+                }
+            }
+            CleanupRule::SplitArtist { separator } => {
+                if !track.artist.trim().is_empty() {
+                    return;
+                }
+                if let Some((artist, title)) = track.title.split_once(separator.as_str()) {
+                    let (artist, title) = (artist.trim().to_string(), title.trim().to_string());
+                    if !artist.is_empty() && !title.is_empty() {
+                        track.artist = artist;
+                        track.title = title;
+                    }
+                }
+            }
+            CleanupRule::StripSuffix { fields, suffixes } => {
+                for field in fields {
+                    let text = track.field_mut(*field);
+                    while let Some(stripped) = suffixes.iter().find_map(|suffix| strip_suffix(text, suffix)) {
+                        *text = stripped;
+                    }
+                }
+            }
+            CleanupRule::Case { fields, case } => {
+                for field in fields {
+                    let text = track.field_mut(*field);
+                    *text = match case {
+                        TextCase::Title => title_case(text),
+                        TextCase::Upper => text.to_uppercase(),
+                        TextCase::Lower => text.to_lowercase(),
+                    };
+                }
+            }
+            CleanupRule::ExtractFeat { fields } => {
+                for field in fields {
+                    if let Some(featured) = extract_feat(track.field_mut(*field)) {
+                        if !track.featuring.is_empty() {
+                            track.featuring.push_str(", ");
+                        }
+                        track.featuring.push_str(&featured);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Run the rules over the track in order, keeping the original values of the fields they change
+pub fn apply(rules: &[CleanupRule], track: &mut DeckStatus) {
+    if rules.is_empty() {
+        return;
+    }
+    let before: Vec<(TrackField, String)> = IntoIterator::into_iter(TrackField::ALL)
+        .map(|field| (field, track.field_mut(field).clone()))
+        .collect();
+    for rule in rules {
+        rule.apply(track);
+    }
+    for (field, value) in before {
+        if track.field_mut(field) != &value {
+            trace!("Cleaned up {:?}: {:?} -> {:?}", field, value, track.field_mut(field));
+            track.original.insert(field, value);
+        }
+    }
+}
+
+/// Text without the suffix and the whitespace before it, if it ends with the suffix in any case
+fn strip_suffix(text: &str, suffix: &str) -> Option<String> {
+    let text = text.trim_end();
+    let start = text.len().checked_sub(suffix.len())?;
+    let tail = text.get(start..)?;
+    if !suffix.is_empty() && tail.to_lowercase() == suffix.to_lowercase() {
+        Some(text[..start].trim_end().to_string())
+    } else {
+        None
+    }
+}
+
+/// Capitalize the first letter of every word and lower the others
+fn title_case(text: &str) -> String {
+    let mut rslt = String::with_capacity(text.len());
+    let mut word_start = true;
+    for c in text.chars() {
+        if word_start {
+            rslt.extend(c.to_uppercase());
+        } else {
+            rslt.extend(c.to_lowercase());
+        }
+        word_start = !(c.is_alphanumeric() || c == '\'');
+    }
+    rslt
+}
+
+/// Remove `(feat. Someone)` from anywhere in the text, or `feat. Someone` from its end, returning who is featured
+fn extract_feat(text: &mut String) -> Option<String> {
+    static FEAT: OnceLock<Regex> = OnceLock::new();
+    let feat = FEAT.get_or_init(|| {
+        Regex::new(r"(?i)\s*[(\[](?:feat\.?|ft\.?|featuring)\s+([^)\]]+)[)\]]|\s+(?:feat\.|ft\.|featuring)\s+(.+)$")
+            .expect("feat. pattern is valid")
+    });
+    let captures = feat.captures(text)?;
+    let featured = captures.get(1).or_else(|| captures.get(2))?.as_str().trim().to_string();
+    let range = captures.get(0)?.range();
+    text.replace_range(range, "");
+    *text = text.trim().to_string();
+    Some(featured)
+}
@@ -0,0 +1,164 @@
+use std::env;
+
+/// Prefix of the environment variables overriding the settings
+const ENV_PREFIX: &str = "TRAKTOR_RELAY_";
+
+/// Options that take a value
+const VALUE_OPTIONS: [&str; 10] = [
+    "config", "bind", "port", "ws-port", "webroot", "log-level", "record", "replay", "speed", "simulate",
+];
+
+/// Options that can also be set from the environment, only the ones picking the server settings
+const ENV_OPTIONS: [&str; 6] = ["config", "bind", "port", "ws-port", "webroot", "log-level"];
+
+const USAGE: &str = "Usage: traktor-obs-relay [OPTIONS]
+
+Options:
+    --config <PATH>       Settings file to use [default: config.toml]
+    --bind <IP>           IP to bind to
+    --port <PORT>         HTTP server port
+    --ws-port <PORT>      Websocket server port
+    --webroot <PATH>      Folder with the widget files
+    --log-level <LEVEL>   Log level (Error, Warn, Info, Debug, Trace)
+    --record <PATH>       Write every call made by Traktor to a session file
+    --replay <PATH>       Feed the calls from a session file to the relay
+    --speed <FACTOR>      Replay speed multiplier, with --replay [default: 1.0]
+    --simulate <PATH>     Simulate Traktor mixing the tracks from a playlist file
+    --print-config        Print the effective configuration and exit
+    --check-config        Validate the configuration and exit, non-zero on problems
+    -h, --help            Print this help and exit
+    -V, --version         Print the version and exit
+
+The --config, --bind, --port, --ws-port, --webroot and --log-level options
+can also be set with an environment variable, e.g. TRAKTOR_RELAY_WS_PORT=9091. Command line options take precedence over
+environment variables, which take precedence over the settings file.";
+
+/// Command line arguments
+#[derive(Debug, Default)]
+pub struct Args {
+    /// Settings file path
+    pub config: Option<String>,
+    /// IP to bind to
+    pub bind: Option<String>,
+    /// HTTP port
+    pub port: Option<u16>,
+    /// Websocket port
+    pub ws_port: Option<u16>,
+    /// Webroot folder
+    pub webroot: Option<String>,
+    /// Log level name
+    pub log_level: Option<String>,
+    /// Session file to record Traktor calls to
+    pub record: Option<String>,
+    /// Session file to replay Traktor calls from
+    pub replay: Option<String>,
+    /// Replay speed multiplier
+    pub speed: Option<f64>,
+    /// Playlist file to simulate Traktor with
+    pub simulate: Option<String>,
+    /// Dump the merged settings instead of starting the servers
+    pub print_config: bool,
+    /// Validate the settings instead of starting the servers
+    pub check_config: bool,
+}
+
+impl Args {
+    /// Get the arguments of the current process, exiting on errors or informational flags
+    pub fn from_env() -> Self {
+        match Self::parse(env::args().skip(1), |name| env::var(format!("{}{}", ENV_PREFIX, name)).ok()) {
+            Ok(args) => args,
+            Err(ArgsError::Help) => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            Err(ArgsError::Version) => {
+                println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+                std::process::exit(0);
+            }
+            Err(ArgsError::Invalid(msg)) => {
+                eprintln!("{}\n\n{}", msg, USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    /// Parse arguments on top of the values provided by the `env_var` lookup
+    pub fn parse<I, E>(args: I, env_var: E) -> Result<Self, ArgsError>
+    where
+        I: IntoIterator<Item = String>,
+        E: Fn(&str) -> Option<String>,
+    {
+        let mut rslt = Self::default();
+        for option in ENV_OPTIONS.iter() {
+            if let Some(value) = env_var(&option.replace('-', "_").to_uppercase()) {
+                rslt.set(option, value)?;
+            }
+        }
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => return Err(ArgsError::Help),
+                "-V" | "--version" => return Err(ArgsError::Version),
+                "--print-config" => rslt.print_config = true,
+                "--check-config" => rslt.check_config = true,
+                _ => {
+                    let (name, inline_value) = match arg.strip_prefix("--") {
+                        Some(opt) => match opt.find('=') {
+                            Some(idx) => (&opt[..idx], Some(opt[idx + 1..].to_string())),
+                            None => (opt, None),
+                        },
+                        None => return Err(ArgsError::Invalid(format!("Unexpected argument: {}", arg))),
+                    };
+                    if !VALUE_OPTIONS.contains(&name) {
+                        return Err(ArgsError::Invalid(format!("Unknown option: --{}", name)));
+                    }
+                    let value = match inline_value.or_else(|| args.next()) {
+                        Some(v) => v,
+                        None => return Err(ArgsError::Invalid(format!("Option --{} requires a value", name))),
+                    };
+                    rslt.set(name, value)?;
+                }
+            }
+        }
+        if rslt.speed.is_some() && rslt.replay.is_none() {
+            return Err(ArgsError::Invalid(String::from("Option --speed requires --replay")));
+        }
+        Ok(rslt)
+    }
+
+    fn set(&mut self, name: &str, value: String) -> Result<(), ArgsError> {
+        let port = |v: &str| {
+            v.parse::<u16>()
+                .map_err(|_| ArgsError::Invalid(format!("Invalid port for --{}: {}", name, v)))
+        };
+        match name {
+            "config" => self.config = Some(value),
+            "bind" => self.bind = Some(value),
+            "port" => self.port = Some(port(&value)?),
+            "ws-port" => self.ws_port = Some(port(&value)?),
+            "webroot" => self.webroot = Some(value),
+            "log-level" => self.log_level = Some(value),
+            "record" => self.record = Some(value),
+            "replay" => self.replay = Some(value),
+            "simulate" => self.simulate = Some(value),
+            "speed" => match value.parse::<f64>() {
+                Ok(speed) if speed > 0.0 => self.speed = Some(speed),
+                _ => return Err(ArgsError::Invalid(format!("Invalid speed for --speed: {}", value))),
+            },
+            _ => unreachable!("Option {} is not handled", name),
+        }
+        Ok(())
+    }
+}
+
+/// Why the arguments did not give settings to run with
+#[derive(Debug)]
+pub enum ArgsError {
+    /// Help was asked for
+    Help,
+    /// Version was asked for
+    Version,
+    /// Arguments that make no sense, with the reason
+    Invalid(String),
+}
@@ -0,0 +1,114 @@
+use super::{
+    api::Deck,
+    app::App,
+    error::{RelayError, Result},
+    http_server::{self, ErrorResponse, NowPlayingResponse, StatusResponse},
+    overrides::OverrideRequest,
+    ws_server::{self, Topic},
+};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::net::SocketAddr;
+
+/// A request sent by a websocket client, with an optional `id` to find the response by
+#[derive(Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "camelCase")]
+enum Command {
+    /// Same as `GET /nowPlaying`
+    GetNowPlaying,
+    /// Same as `GET /status`
+    GetStatus,
+    /// Tracks played on air, oldest first
+    GetHistory {
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+    /// Same as `GET /mixHints`
+    GetMixHints,
+    /// Same as `GET /render/{name}` if `name` is given, or else `GET /render?template=...`
+    Render {
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(default)]
+        template: Option<String>,
+    },
+    /// Same as `GET /override/{deck}`
+    GetOverride { deck: Deck },
+    /// Same as `POST /override/{deck}`, or `DELETE /override/{deck}` with `"clear": true`
+    SetOverride {
+        deck: Deck,
+        #[serde(flatten)]
+        request: Box<OverrideRequest>,
+    },
+    /// Only receive the listed kinds of messages, or all of them if `events` is missing
+    Subscribe {
+        #[serde(default)]
+        events: Option<HashSet<Topic>>,
+    },
+}
+
+/// Answer to a command
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CommandResponse {
+    pub event: &'static str,
+    /// `id` of the command, `null` if there was none
+    pub id: Value,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorResponse>,
+}
+
+/// Run a command sent by the websocket client at `addr`, returning the response to send back
+pub fn handle(app: &App, addr: SocketAddr, text: &str) -> Result<String> {
+    trace!("WS command from {}: {}", addr, text);
+    let mut request: Value = serde_json::from_str(text).unwrap_or(Value::Null);
+    // The id belongs to the envelope, the commands that take any field must not see it
+    let id = request.as_object_mut().and_then(|fields| fields.remove("id")).unwrap_or(Value::Null);
+
+    let rslt = serde_json::from_value::<Command>(request)
+        .map_err(|e| RelayError::BadRequest(format!("not a command: {}", e)))
+        .and_then(|command| run(app, addr, command));
+    let response = match rslt {
+        Ok(result) => CommandResponse { event: "response", id, ok: true, result: Some(result), error: None },
+        Err(e) => {
+            debug!("WS command from {} failed: {}", addr, e);
+            CommandResponse { event: "response", id, ok: false, result: None, error: Some(ErrorResponse::from(&e)) }
+        }
+    };
+    Ok(serde_json::to_string(&response)?)
+}
+
+fn run(app: &App, addr: SocketAddr, command: Command) -> Result<Value> {
+    debug!("WS command from {}: {:?}", addr, command);
+    let rslt = match command {
+        Command::GetNowPlaying => {
+            let state = app.state()?;
+            serde_json::to_value(NowPlayingResponse::create(&state, &app.settings().mixing))?
+        }
+        Command::GetStatus => serde_json::to_value(StatusResponse::create(app)?)?,
+        Command::GetHistory { limit } => serde_json::to_value(app.state()?.history(limit))?,
+        Command::GetMixHints => serde_json::to_value(app.state()?.mix_hints(&app.settings().mixing))?,
+        Command::Render { name, template } => {
+            let text = match (name, template) {
+                (Some(name), _) => http_server::render_named(app, &name)?,
+                (None, Some(template)) => http_server::render_template(app, &template)?,
+                (None, None) => return Err(RelayError::BadRequest(String::from("either name or template is required"))),
+            };
+            Value::String(text)
+        }
+        Command::GetOverride { deck } => {
+            let overrides = app.state()?.overrides(&deck).ok_or(RelayError::UnknownDeck(deck))?;
+            serde_json::to_value(overrides)?
+        }
+        Command::SetOverride { deck, request } => serde_json::to_value(http_server::set_override(app, &deck, *request)?)?,
+        Command::Subscribe { events } => {
+            let rslt = serde_json::json!({ "events": events });
+            ws_server::set_topics(app, &addr, events)?;
+            rslt
+        }
+    };
+    Ok(rslt)
+}
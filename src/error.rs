@@ -0,0 +1,126 @@
+use super::{api::Deck, settings::SettingsError};
+use std::fmt;
+
+/// Errors that can happen while serving the relay clients
+#[derive(Debug)]
+pub enum RelayError {
+    /// Shared state lock was poisoned by a panicking thread
+    StatePoisoned,
+    /// Request could not be understood
+    BadRequest(String),
+    /// Requested deck has no status
+    UnknownDeck(Deck),
+    /// Requested resource was not found
+    NotFound(String),
+    /// Resource exists but is not of the expected type
+    NotAcceptable(String),
+    /// Settings could not be applied
+    Settings(SettingsError),
+    /// File system error
+    Io(std::io::Error),
+    /// Message could not be serialized
+    Serialization(serde_json::Error),
+    /// Websocket protocol error
+    WebSocket(Box<tokio_tungstenite::tungstenite::Error>),
+    /// HTTP server error
+    Http(hyper::Error),
+    /// OBS did not do what it was asked to
+    Obs(String),
+    /// Streaming server did not take the song title
+    Stream(String),
+}
+
+/// Result type for the relay
+pub type Result<T> = std::result::Result<T, RelayError>;
+
+impl RelayError {
+    /// Machine readable error code
+    pub fn code(&self) -> &'static str {
+        match self {
+            RelayError::StatePoisoned => "statePoisoned",
+            RelayError::BadRequest(_) => "badRequest",
+            RelayError::UnknownDeck(_) => "unknownDeck",
+            RelayError::NotFound(_) => "notFound",
+            RelayError::NotAcceptable(_) => "notAcceptable",
+            RelayError::Settings(_) => "invalidSettings",
+            RelayError::Io(_) => "io",
+            RelayError::Serialization(_) => "serialization",
+            RelayError::WebSocket(_) => "webSocket",
+            RelayError::Http(_) => "http",
+            RelayError::Obs(_) => "obs",
+            RelayError::Stream(_) => "stream",
+        }
+    }
+
+    /// HTTP status code to respond with
+    pub fn status_code(&self) -> u16 {
+        match self {
+            RelayError::BadRequest(_) | RelayError::Settings(_) => 400,
+            RelayError::UnknownDeck(_) | RelayError::NotFound(_) => 404,
+            RelayError::NotAcceptable(_) => 406,
+            RelayError::StatePoisoned
+            | RelayError::Io(_)
+            | RelayError::Serialization(_)
+            | RelayError::WebSocket(_)
+            | RelayError::Http(_) => 500,
+            RelayError::Obs(_) | RelayError::Stream(_) => 502,
+        }
+    }
+}
+
+impl fmt::Display for RelayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RelayError::StatePoisoned => write!(f, "Relay state is unavailable after a previous failure"),
+            RelayError::BadRequest(msg) => write!(f, "Bad request: {}", msg),
+            RelayError::UnknownDeck(deck) => write!(f, "Deck {} is not known", deck),
+            RelayError::NotFound(what) => write!(f, "Not found: {}", what),
+            RelayError::NotAcceptable(msg) => write!(f, "Not acceptable: {}", msg),
+            RelayError::Settings(e) => write!(f, "Settings error: {}", e),
+            RelayError::Io(e) => write!(f, "I/O error: {}", e),
+            RelayError::Serialization(e) => write!(f, "Serialization error: {}", e),
+            RelayError::WebSocket(e) => write!(f, "Websocket error: {}", e),
+            RelayError::Http(e) => write!(f, "HTTP error: {}", e),
+            RelayError::Obs(msg) => write!(f, "OBS error: {}", msg),
+            RelayError::Stream(msg) => write!(f, "Streaming server error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for RelayError {}
+
+impl<T> From<std::sync::PoisonError<T>> for RelayError {
+    fn from(_: std::sync::PoisonError<T>) -> Self {
+        RelayError::StatePoisoned
+    }
+}
+
+impl From<SettingsError> for RelayError {
+    fn from(e: SettingsError) -> Self {
+        RelayError::Settings(e)
+    }
+}
+
+impl From<std::io::Error> for RelayError {
+    fn from(e: std::io::Error) -> Self {
+        RelayError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for RelayError {
+    fn from(e: serde_json::Error) -> Self {
+        RelayError::Serialization(e)
+    }
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for RelayError {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        RelayError::WebSocket(Box::new(e))
+    }
+}
+
+impl From<hyper::Error> for RelayError {
+    fn from(e: hyper::Error) -> Self {
+        RelayError::Http(e)
+    }
+}
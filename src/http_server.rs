@@ -1,44 +1,50 @@
 use super::{
     api::{channel::*, deck::*, master_clock::*, Channel, Deck},
-    settings, CHANNEL_STATUS, DECK_STATUS, MASTER_CLOCK,
+    app::App,
+    cleanup,
+    error::{RelayError, Result},
+    logic::Artwork,
+    metrics,
+    mix_hints::MixHint,
+    obs,
+    overrides::{self, DeckOverrides, OverrideRequest, OverrideScope},
+    settings::{self, MixingSettings, ServerSettings},
+    state::RelayState,
+    stats::{self, StatsEntry, TopOrder},
+    status::Ingest,
+    template,
+    throttle::{self, Verdict},
+    ws_server::{self, ws_push, Topic},
 };
-use rouille::Response;
-use std::collections::HashMap;
-
-pub fn spawn_http() {
-    std::thread::spawn(move || {
-        debug!("Starting http server thread");
-        start_http();
-    });
-}
+use hyper::body::Bytes;
+use hyper::header::{self, HeaderValue};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::Serialize;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase", default)]
-struct NowPlayingResponse {
+pub(crate) struct NowPlayingResponse {
     pub bpm: f32,
     pub songs_on_air: Vec<DeckStatus>,
     pub ticked_deck: Option<Deck>
 }
 
 impl NowPlayingResponse {
-    pub fn create(
-        clock: &MasterClock,
-        cur_decks: &HashMap<Deck, DeckStatus>,
-        cur_chans: &HashMap<Channel, ChannelStatus>,
-    ) -> Self {
-        let bpm = clock.bpm;
-        let songs_on_air = super::logic::get_songs_on_air(cur_decks, cur_chans);
+    pub fn create(state: &RelayState, mixing: &MixingSettings) -> Self {
+        let bpm = state.clock.bpm;
+        let songs_on_air = state.songs_on_air(mixing);
         Self { songs_on_air, bpm, ticked_deck: None }
     }
 
-    pub fn tick(
-        clock: &MasterClock,
-        cur_decks: &HashMap<Deck, DeckStatus>,
-        cur_chans: &HashMap<Channel, ChannelStatus>,
-        tick_reason: Deck
-    ) -> Self {
-        let bpm = clock.bpm;
-        let songs_on_air = super::logic::get_songs_on_air(cur_decks, cur_chans);
+    pub fn tick(state: &RelayState, mixing: &MixingSettings, tick_reason: Deck) -> Self {
+        let bpm = state.clock.bpm;
+        let songs_on_air = state.songs_on_air(mixing);
         Self { songs_on_air, bpm, ticked_deck: Some(tick_reason) }
     }
 }
@@ -48,13 +54,482 @@ impl NowPlayingResponse {
 struct BpmResponse {
     pub bpm: f32,
     pub master_deck: Option<Deck>,
+    pub decks: Vec<DeckTempo>,
 }
 
 impl BpmResponse {
-    pub fn from(clock: &MasterClock) -> Self {
+    pub fn create(state: &RelayState, mixing: &MixingSettings) -> Self {
         Self {
-            bpm: clock.bpm,
-            master_deck: clock.deck.clone(),
+            bpm: state.clock.bpm,
+            master_deck: state.clock.deck.clone(),
+            decks: state.deck_tempos(mixing),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase", default)]
+struct ConfigChangedEvent {
+    pub event: &'static str,
+    pub restart_required: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct LastUpdates {
+    pub deck_loaded: Option<f64>,
+    pub update_deck: Option<f64>,
+    pub update_master_clock: Option<f64>,
+    pub update_channel: Option<f64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct StatusResponse {
+    pub version: &'static str,
+    pub uptime: f64,
+    pub traktor_connected: bool,
+    /// Seconds since each of the Traktor calls was last made
+    pub last_update: LastUpdates,
+    pub ws_peers: usize,
+    pub obs_connected: bool,
+    pub decks: Vec<Deck>,
+}
+
+impl StatusResponse {
+    pub fn create(app: &App) -> Result<Self> {
+        let settings = app.settings();
+        let state = app.state()?;
+        let since = |ingest| state.since(ingest).map(|d| d.as_secs_f64());
+        Ok(Self {
+            version: env!("CARGO_PKG_VERSION"),
+            uptime: app.uptime().as_secs_f64(),
+            traktor_connected: state.traktor_connected(std::time::Duration::from_secs(settings.mixing.traktor_timeout)),
+            last_update: LastUpdates {
+                deck_loaded: since(Ingest::DeckLoaded),
+                update_deck: since(Ingest::UpdateDeck),
+                update_master_clock: since(Ingest::UpdateMasterClock),
+                update_channel: since(Ingest::UpdateChannel),
+            },
+            ws_peers: ws_server::peer_count(app),
+            obs_connected: app.obs.is_connected(),
+            decks: settings.mixing.deck_list.clone(),
+        })
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct ErrorResponse {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl ErrorResponse {
+    pub fn from(err: &RelayError) -> Self {
+        Self {
+            code: err.code(),
+            message: err.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OverrideChangedEvent<'a> {
+    pub event: &'static str,
+    pub deck: &'a str,
+    pub overrides: &'a DeckOverrides,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BpmSettledEvent {
+    pub event: &'static str,
+    pub bpm: f32,
+    pub master_deck: Option<Deck>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MixHintsEvent {
+    pub event: &'static str,
+    pub hints: Vec<MixHint>,
+}
+
+impl MixHintsEvent {
+    pub fn from(hints: Vec<MixHint>) -> Self {
+        Self { event: "mixHints", hints }
+    }
+}
+
+impl ConfigChangedEvent {
+    pub fn from(report: settings::ReloadReport) -> Self {
+        Self {
+            event: "configChanged",
+            restart_required: report.restart_required,
+        }
+    }
+}
+
+/// Let the output files and the stream song titles catch up with a change of the mix
+fn refresh_outputs(app: &App) {
+    app.outputs.refresh();
+    app.streams.refresh();
+}
+
+/// Same for a master clock change, which only matters to the templates showing it
+fn refresh_clock_outputs(app: &App, settings: &ServerSettings) {
+    const CLOCK: [&str; 2] = ["bpm", "masterDeck"];
+    if settings.outputs.files.values().any(|template| template::uses(template, &CLOCK)) {
+        app.outputs.refresh();
+    }
+    if settings.outputs.streams.iter().any(|stream| template::uses(&stream.song, &CLOCK)) {
+        app.streams.refresh();
+    }
+}
+
+/// Reload the settings file and let the clients know about the new configuration
+pub fn reload_settings(app: &App) -> Result<()> {
+    let report = app.reload_settings()?;
+    if !report.restart_required.is_empty() {
+        warn!("Settings require restart to take effect: {}", report.restart_required.join(", "));
+    }
+    ws_push(app, Topic::ConfigChanged, &ConfigChangedEvent::from(report));
+
+    // Deck list or channel map might have changed what is on air
+    let settings = app.settings();
+    let now_playing = {
+        let mut state = app.state_mut()?;
+        state.preheat_channels(&settings.mixing);
+        NowPlayingResponse::create(&state, &settings.mixing)
+    };
+    ws_push(app, Topic::NowPlaying, &now_playing);
+    refresh_outputs(app);
+    Ok(())
+}
+
+/// Watch the settings file for modifications and reload it when it was changed
+pub fn spawn_settings_watcher(app: Arc<App>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        debug!("Starting settings watcher");
+        let mut last_modified = app.settings_source().modified_at();
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = app.shutdown_requested() => break,
+            }
+            let modified = app.settings_source().modified_at();
+            if modified == last_modified {
+                continue;
+            }
+            debug!("Settings file modification detected");
+            last_modified = modified;
+            let reload_app = app.clone();
+            match tokio::task::spawn_blocking(move || reload_settings(&reload_app)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => error!("Settings were not reloaded: {}", e),
+                Err(e) => error!("Settings reload panicked: {}", e),
+            }
+        }
+        debug!("Settings watcher stopped");
+    })
+}
+
+/// Change the metadata override of the deck and let the clients know
+pub fn set_override(app: &App, deck: &str, request: OverrideRequest) -> Result<DeckOverrides> {
+    debug!("Set {:?} override of deck {}: {:?}", request.scope, deck, request.metadata);
+    let settings = app.settings();
+    let (overrides, now_playing, file_overrides) = {
+        let mut state = app.state_mut()?;
+        let overrides = state
+            .set_override(deck, request.scope, request.metadata)
+            .ok_or_else(|| RelayError::UnknownDeck(deck.to_string()))?;
+        let file_overrides = match request.scope {
+            OverrideScope::File => Some(state.file_overrides.clone()),
+            OverrideScope::Deck => None,
+        };
+        (overrides, NowPlayingResponse::create(&state, &settings.mixing), file_overrides)
+    };
+
+    if let Some(file_overrides) = file_overrides {
+        overrides::save(&settings.mixing.overrides_file, &file_overrides)?;
+    }
+    ws_push(app, Topic::OverrideChanged, &OverrideChangedEvent {
+        event: "overrideChanged",
+        deck,
+        overrides: &overrides,
+    });
+    ws_push(app, Topic::NowPlaying, &now_playing);
+    refresh_outputs(app);
+    Ok(overrides)
+}
+
+/// Render the template against the current track and the master clock
+pub fn render_template(app: &App, template: &str) -> Result<String> {
+    let settings = app.settings();
+    let state = app.state()?;
+    let track = state.current_track(&settings.mixing);
+    let values = template::values(track.as_ref(), &state.clock, settings.http.bpm_precision);
+    Ok(template::render(template, &values))
+}
+
+/// Render the template configured under `name`
+pub fn render_named(app: &App, name: &str) -> Result<String> {
+    let settings = app.settings();
+    let template = settings.templates.get(name).ok_or_else(|| RelayError::NotFound(format!("template {}", name)))?;
+    render_template(app, template)
+}
+
+/// Push the master clock change that waited for the rate limit if `retry` is set,
+/// and let the clients know that the BPM has settled if `settled` is set
+pub(crate) fn flush_bpm(app: &App, retry: bool, settled: bool) -> Result<()> {
+    let settings = app.settings();
+    let mut bpm = BpmResponse::create(&*app.state()?, &settings.mixing);
+    if retry {
+        if let Verdict::Push(rounded) = app.bpm_throttle.offer(bpm.bpm, bpm.master_deck.as_ref(), &settings.http, Instant::now()) {
+            bpm.bpm = rounded;
+            ws_push(app, Topic::Bpm, &bpm);
+            refresh_clock_outputs(app, &settings);
+        }
+    }
+    if settled {
+        ws_push(app, Topic::BpmSettled, &BpmSettledEvent {
+            event: "bpmSettled",
+            bpm: throttle::round(bpm.bpm, settings.http.bpm_precision),
+            master_deck: bpm.master_deck,
+        });
+    }
+    Ok(())
+}
+
+/// Handle a call made by Traktor, `id` being the deck or channel in the route if any
+pub fn ingest(app: &App, kind: Ingest, id: Option<String>, body: serde_json::Value) -> Result<()> {
+    app.state_mut()?.record_ingest(kind);
+    app.metrics.count_ingest(kind);
+    app.record(kind, id.as_deref(), &body);
+
+    let id = || id.clone().ok_or_else(|| RelayError::BadRequest(format!("{} requires an id", kind.route())));
+    let parse_err = |e: serde_json::Error| RelayError::BadRequest(e.to_string());
+    let settings = app.settings();
+    let mut play_counted = false;
+    match kind {
+        Ingest::DeckLoaded => {
+            let id: Deck = id()?;
+            let mut new_status: DeckStatus = serde_json::from_value(body).map_err(parse_err)?;
+            debug!("Loaded deck {} {:?}", id, new_status);
+            cleanup::apply(&settings.cleanup, &mut new_status);
+            let (now_playing, mix_hints) = {
+                let mut state = app.state_mut()?;
+                let hints_before = state.mix_hints(&settings.mixing);
+                state.load_deck(id, new_status);
+                play_counted = state.update_history(&settings.mixing);
+                let hints = state.mix_hints(&settings.mixing);
+                let mix_hints = if hints != hints_before { Some(MixHintsEvent::from(hints)) } else { None };
+                (NowPlayingResponse::create(&state, &settings.mixing), mix_hints)
+            };
+            ws_push(app, Topic::NowPlaying, &now_playing);
+            refresh_outputs(app);
+            if let Some(mix_hints) = mix_hints {
+                ws_push(app, Topic::MixHints, &mix_hints);
+            }
+        }
+
+        Ingest::UpdateDeck => {
+            let id: Deck = id()?;
+            let new_status: DeckStatusUpdate = serde_json::from_value(body).map_err(parse_err)?;
+            debug!("Updated deck {}: {:?}", id, new_status);
+            let mixing_changed = new_status.tempo.is_some() || new_status.resulting_key.is_some();
+            // Only starting or stopping a deck changes what is playing on air
+            let playing_changed = new_status.is_playing.is_some();
+            let (tick, mix_hints) = {
+                let mut state = app.state_mut()?;
+                let hints_before = if mixing_changed { state.mix_hints(&settings.mixing) } else { vec![] };
+                let changed = state.update_deck(&id, new_status, settings.http.more_events);
+                if playing_changed {
+                    play_counted = state.update_history(&settings.mixing);
+                } else {
+                    state.update_airtime();
+                }
+                let hints = if mixing_changed { state.mix_hints(&settings.mixing) } else { vec![] };
+                let mix_hints = if hints != hints_before { Some(MixHintsEvent::from(hints)) } else { None };
+                let tick = match changed {
+                    Some(true) => Some(NowPlayingResponse::tick(&state, &settings.mixing, id)),
+                    Some(false) => None,
+                    None => {
+                        error!("Deck {} is not known (yet) but update event was received!", id);
+                        None
+                    }
+                };
+                (tick, mix_hints)
+            };
+            if let Some(tick) = tick {
+                ws_push(app, Topic::Tick, &tick);
+                refresh_outputs(app);
+            }
+            if let Some(mix_hints) = mix_hints {
+                ws_push(app, Topic::MixHints, &mix_hints);
+            }
+        }
+
+        Ingest::UpdateMasterClock => {
+            let new_clock: MasterClock = serde_json::from_value(body).map_err(parse_err)?;
+            debug!("Update clock {:?}", new_clock);
+            let mut bpm = {
+                let mut state = app.state_mut()?;
+                state.clock = new_clock;
+                BpmResponse::create(&state, &settings.mixing)
+            };
+            match app.bpm_throttle.offer(bpm.bpm, bpm.master_deck.as_ref(), &settings.http, Instant::now()) {
+                Verdict::Push(rounded) => {
+                    bpm.bpm = rounded;
+                    ws_push(app, Topic::Bpm, &bpm);
+                    refresh_clock_outputs(app, &settings);
+                }
+                Verdict::Wait => trace!("Master clock push waits for the rate limit"),
+                Verdict::Skip => trace!("Master clock change is too small to push"),
+            }
+        }
+
+        Ingest::UpdateChannel => {
+            let id: Channel = id()?
+                .parse()
+                .map_err(|_| RelayError::BadRequest(String::from("channel id must be a number")))?;
+            let new_status: ChannelStatus = serde_json::from_value(body).map_err(parse_err)?;
+            debug!("Update channel {}: {:?}",id, new_status);
+            let now_playing = {
+                let mut state = app.state_mut()?;
+                state.channels.insert(id, new_status);
+                play_counted = state.update_history(&settings.mixing);
+                NowPlayingResponse::create(&state, &settings.mixing)
+            };
+            ws_push(app, Topic::NowPlaying, &now_playing);
+            refresh_outputs(app);
+        }
+    }
+    if play_counted {
+        app.save_stats();
+    }
+    if !matches!(kind, Ingest::UpdateMasterClock) {
+        obs::observe(app)?;
+    }
+    Ok(())
+}
+
+/// An HTTP request with its body read, ready to be handled off the async runtime
+struct HttpRequest {
+    method: Method,
+    path: String,
+    query: String,
+    content_type: Option<String>,
+    body: Bytes,
+}
+
+impl HttpRequest {
+    /// Path segments after the leading slash
+    fn segments(&self) -> Vec<&str> {
+        self.path.trim_start_matches('/').split('/').collect()
+    }
+
+    /// Decoded value of a query string parameter
+    fn query_param(&self, name: &str) -> Option<String> {
+        self.query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .and_then(|(_, value)| percent_decode(&value.replace('+', " ")))
+    }
+
+    /// Read the request body as JSON
+    fn json_body(&self) -> Result<serde_json::Value> {
+        match &self.content_type {
+            Some(content_type) if content_type.starts_with("application/json") => {}
+            _ => return Err(RelayError::BadRequest(String::from("the request's content type is not JSON"))),
+        }
+        serde_json::from_slice(&self.body)
+            .map_err(|e| RelayError::BadRequest(format!("error while parsing the JSON body: {}", e)))
+    }
+}
+
+/// Route name to group the handler metrics by
+fn route_label(url: &str) -> &'static str {
+    let first_segment = url.trim_start_matches('/').split('/').next().unwrap_or_default();
+    match first_segment {
+        "" => "/",
+        "admin" => "admin",
+        "deckLoaded" => "deckLoaded",
+        "updateDeck" => "updateDeck",
+        "updateMasterClock" => "updateMasterClock",
+        "updateChannel" => "updateChannel",
+        "status" => "status",
+        "metrics" => "metrics",
+        "nowPlaying" => "nowPlaying",
+        "history" => "history",
+        "mixHints" => "mixHints",
+        "stats" => "stats",
+        "render" => "render",
+        "override" => "override",
+        "artwork" => "artwork",
+        "subtitles" => "subtitles",
+        "video" => "video",
+        "filename" => "filename",
+        _ => "assets",
+    }
+}
+
+/// Response with the data of the specified mime type
+fn data(mime: &str, data: impl Into<Body>) -> Response<Body> {
+    let mut response = Response::new(data.into());
+    if let Ok(value) = HeaderValue::from_str(mime) {
+        response.headers_mut().insert(header::CONTENT_TYPE, value);
+    }
+    response
+}
+
+/// Plain text response
+fn text(text: String) -> Response<Body> {
+    data("text/plain; charset=utf-8", text)
+}
+
+/// JSON response
+fn json(value: &impl Serialize) -> Result<Response<Body>> {
+    Ok(data("application/json", serde_json::to_vec(value)?))
+}
+
+/// Successful response without a body
+fn empty_204() -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::NO_CONTENT;
+    response
+}
+
+/// Forbid the client from caching the response
+fn no_cache(mut response: Response<Body>) -> Response<Body> {
+    let headers = response.headers_mut();
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache, no-store, must-revalidate"));
+    headers.insert(header::EXPIRES, HeaderValue::from_static("0"));
+    headers.insert(header::PRAGMA, HeaderValue::from_static("no-cache"));
+    response
+}
+
+/// Turn the handler result into a response, describing the error in JSON if there was one
+fn respond<F>(handler: F) -> Response<Body>
+where
+    F: FnOnce() -> Result<Response<Body>>,
+{
+    match handler() {
+        Ok(response) => response,
+        Err(e) => {
+            if e.status_code() >= 500 {
+                error!("{}", e);
+            } else {
+                debug!("{}", e);
+            }
+            let body = serde_json::to_vec(&ErrorResponse::from(&e)).unwrap_or_default();
+            let mut response = no_cache(data("application/json", body));
+            *response.status_mut() = StatusCode::from_u16(e.status_code()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            response
         }
     }
 }
@@ -78,178 +553,369 @@ fn format_time(duration: std::time::Duration) -> String {
     }
 }
 
-fn start_http() {
-    let cfg = &settings::ServerSettings::shared();
-    let host = &cfg.http.bind;
-    let port = &cfg.http.port;
-    let root = cfg.http.webroot.clone();
-    let default_cover = cfg.mixing.default_cover.clone();
-    info!("Start HTTP at {}:{} in {}", host, port, root);
-
-    let log_ok = |req: &rouille::Request, resp: &Response, elapsed: std::time::Duration| {
-        info!("{} {}: rslt={} time={}", req.method(), req.raw_url(), resp.status_code, format_time(elapsed));
-    };
-    let log_err = |req: &rouille::Request, _elap: std::time::Duration| {
-        error!("Handler panicked: {} {}", req.method(), req.raw_url());
-    };
+/// Decode the %-escapes in a URL path
+fn percent_decode(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut rslt = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        if bytes[idx] == b'%' {
+            let hex = text.get(idx + 1..idx + 3)?;
+            rslt.push(u8::from_str_radix(hex, 16).ok()?);
+            idx += 3;
+        } else {
+            rslt.push(bytes[idx]);
+            idx += 1;
+        }
+    }
+    String::from_utf8(rslt).ok()
+}
 
-    rouille::start_server(format!("{}:{}", host, port), move |request| {
-        rouille::log_custom(&request, log_ok, log_err, || {
-            router!(request,
-                (GET) (/) => {
-                    Response::text("Point traktor API or OBS here")
-                },
+/// Mime type of a web asset by its file extension
+fn extension_to_mime(extension: &str) -> &'static str {
+    match extension.to_lowercase().as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "js" => "application/javascript",
+        "css" => "text/css; charset=utf-8",
+        "json" => "application/json",
+        "wasm" => "application/wasm",
+        "txt" | "ass" | "ssa" | "srt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mp3" => "audio/mpeg",
+        _ => "application/octet-stream",
+    }
+}
 
-                (POST) (/deckLoaded/{id: Deck}) => {
-                    trace!("Deck load API call");
-                    let mut new_status: DeckStatus = try_or_400!(rouille::input::json_input(request));
-                    new_status.deck = Some(id.clone());
-                    debug!("Loaded deck {} {:?}", id, new_status);
-                    let mut decks = DECK_STATUS.write().expect("RwLock failed");
-                    decks.insert(id, new_status);
-                    let chans = CHANNEL_STATUS.read().expect("RwLock failed");
-                    let clock = MASTER_CLOCK.read().expect("RwLock failed");
-                    super::ws_server::ws_push(&NowPlayingResponse::create(&clock, &decks, &chans));
-                    Response::empty_204()
-                },
+/// Artwork of the track loaded into the deck: embedded into the file, or an image next to it.
+/// The default cover art is used if there is none, or if the track is hidden.
+pub(crate) fn deck_artwork(app: &App, state: &RelayState, deck_id: &str) -> Result<Artwork> {
+    let deck_id = deck_id.to_string();
+    let settings = app.settings();
+    let decks = &state.decks;
+    if state.is_hidden(&deck_id, &settings.mixing) {
+        return default_artwork(&settings.mixing, &deck_id);
+    }
 
-                (POST) (/updateDeck/{id: Deck}) => {
-                    trace!("Deck update API call");
-                    let new_status: DeckStatusUpdate = try_or_400!(rouille::input::json_input(request));
-                    debug!("Updated deck {}: {:?}", id, new_status);
-                    let mut decks = DECK_STATUS.write().expect("RwLock failed");
-
-                    if let Some(deck) = decks.get_mut(&id) {
-                        if deck.update(new_status) {
-                            let chans = CHANNEL_STATUS.read().expect("RwLock failed");
-                            let clock = MASTER_CLOCK.read().expect("RwLock failed");
-                            super::ws_server::ws_push(&NowPlayingResponse::tick(&clock, &decks, &chans, id));
-                        }
-                    }
-                    else {
-                        error!("Deck {} is not known (yet) but update event was received!", id);
+    match super::logic::get_deck_artwork(&deck_id, decks, &app.artwork, &app.metrics) {
+        None => {
+            for ftype in [ ("jpg", "image/jpeg"), ("jpeg", "image/jpeg"), ("png", "image/png") ].iter() {
+                match super::logic::get_deck_assoc_file(&deck_id, decks, ftype.0) {
+                    None => continue,
+                    Some(contents) => {
+                        return Ok(Artwork { mime_type: ftype.1.to_string(), data: contents })
                     }
-                    Response::empty_204()
-                },
+                }
+            }
+            default_artwork(&settings.mixing, &deck_id)
+        },
+        Some(art) => {
+            trace!("Sending artwork for deck {}", deck_id);
+            Ok(art)
+        }
+    }
+}
 
-                (POST) (/updateMasterClock) => {
-                    trace!("Clock update API call");
-                    let new_clock: MasterClock = try_or_400!(rouille::input::json_input(request));
-                    debug!("Update clock {:?}", new_clock);
-                    super::ws_server::ws_push(&BpmResponse::from(&new_clock));
-                    *(MASTER_CLOCK.write().expect("RwLock failed")) = new_clock;
-                    Response::empty_204()
-                },
+/// The default cover art, in place of the artwork of the deck
+pub(crate) fn default_artwork(mixing: &MixingSettings, deck_id: &str) -> Result<Artwork> {
+    let file_path = std::path::Path::new(&mixing.default_cover);
+    if !file_path.exists() {
+        return Err(RelayError::NotFound(format!("default artwork file {}", file_path.display())));
+    }
+    match infer::get_from_path(file_path)? {
+        Some(mime) if mime.matcher_type() == infer::MatcherType::IMAGE => {
+            trace!("Sending default artwork for deck {}", deck_id);
+            Ok(Artwork { mime_type: mime.mime_type().to_string(), data: std::fs::read(file_path)? })
+        },
+        Some(mime) => {
+            Err(RelayError::NotAcceptable(format!("file {} is not an image file: {}", file_path.display(), mime)))
+        },
+        None => {
+            Err(RelayError::NotAcceptable(format!("could not find mime type of {}", file_path.display())))
+        }
+    }
+}
 
-                (POST) (/updateChannel/{id: Channel}) => {
-                    trace!("Update channel API call");
-                    let new_status: ChannelStatus = try_or_400!(rouille::input::json_input(request));
-                    debug!("Update channel {}: {:?}",id, new_status);
-                    let mut chans = CHANNEL_STATUS.write().expect("RwLock failed");
-                    chans.insert(id, new_status);
-                    let clock = MASTER_CLOCK.read().expect("RwLock failed");
-                    let decks = DECK_STATUS.read().expect("RwLock failed");
-
-                    super::ws_server::ws_push(&NowPlayingResponse::create(&clock, &decks, &chans));
-                    Response::empty_204()
-                },
+/// Serve a file from the webroot
+fn serve_asset(webroot: &str, path: &str) -> Result<Response<Body>> {
+    let not_found = || RelayError::NotFound(format!("asset {}", path));
+    let relative = percent_decode(path).ok_or_else(not_found)?;
+    if relative.split(['/', '\\']).any(|segment| segment == "..") {
+        return Err(not_found());
+    }
 
-                (GET) (/nowPlaying) => {
-                    trace!("Now playing info API call");
-                    let chans = CHANNEL_STATUS.read().expect("RwLock failed");
-                    let clock = MASTER_CLOCK.read().expect("RwLock failed");
-                    let decks = DECK_STATUS.read().expect("RwLock failed");
-                    Response::json(&NowPlayingResponse::create(&clock, &decks, &chans))
-                },
+    let file_path = std::path::Path::new(webroot).join(relative.trim_start_matches('/'));
+    if !file_path.is_file() {
+        return Err(not_found());
+    }
+    let extension = file_path.extension().map(|ext| ext.to_string_lossy().into_owned()).unwrap_or_default();
+    trace!("Sending asset {}", file_path.display());
+    Ok(no_cache(data(extension_to_mime(&extension), std::fs::read(&file_path)?)))
+}
 
-                (GET) (/artwork/{deck_id: Deck}) => {
-                    trace!("Artwork get over HTTP");
-                    let decks = DECK_STATUS.read().expect("RwLock failed");
-
-                    match super::logic::get_deck_artwork(&deck_id, &decks) {
-                        None => {
-                            for ftype in [ ("jpg", "image/jpeg"), ("jpeg", "image/jpeg"), ("png", "image/png") ].iter() {
-                                match super::logic::get_deck_assoc_file(&deck_id, &decks, ftype.0) {
-                                    None => continue,
-                                    Some(data) => {
-                                        return Response::from_data(ftype.1, data).with_no_cache()
-                                    }
-                                }
-                            }
-
-                            let file_path = std::path::Path::new(&default_cover);
-                            if file_path.exists() {
-                                if let Ok(Some(mime)) = infer::get_from_path(file_path) {
-                                    if mime.matcher_type() == infer::MatcherType::IMAGE {
-                                        trace!("Sending default artwork for deck {}", deck_id);
-                                        return Response::from_file(mime.mime_type(), std::fs::File::open(file_path).unwrap()).with_no_cache();
-                                    }
-                                    else {
-                                        error!("File {} is not an image file: {}", file_path.display(), mime);
-                                        return Response::empty_406().with_no_cache();
-                                    }
-                                }
-                                else {
-                                    error!("Could not find mime type of {}", file_path.display());
-                                    return Response::empty_406().with_no_cache();
-                                }
-                            }
-                            else {
-                                error!("Could not find default artwork file: {}", file_path.display());
-                                return Response::empty_404().with_no_cache();
-                            }
-                        },
-                        Some(art) => {
-                            trace!("Sending artwork for deck {}", deck_id);
-                            Response::from_data(art.mime_type, art.data).with_no_cache()
-                        }
-                    }
-                },
+/// Find the handler for the request and run it
+fn route(app: &App, request: &HttpRequest) -> Result<Response<Body>> {
+    match (&request.method, request.segments().as_slice()) {
+        (&Method::GET, [""]) => {
+            Ok(text(String::from("Point traktor API or OBS here")))
+        },
 
-                (GET) (/subtitles/{deck_id: Deck}) => {
-                    trace!("Subtitles get over HTTP");
-                    let decks = DECK_STATUS.read().expect("RwLock failed");
-                    match super::logic::get_deck_assoc_file(&deck_id, &decks, "ass") {
-                        None => {
-                            Response::empty_404().with_no_cache()
-                        },
-                        Some(text) => {
-                            Response::from_data("text/plain", text).with_no_cache()
-                        }
-                    }
-                },
+        (&Method::POST, ["admin", "reload"]) => {
+            trace!("Settings reload API call");
+            reload_settings(app)?;
+            Ok(empty_204())
+        },
 
-                (GET) (/video/{deck_id: Deck}) => {
-                    trace!("Video get over HTTP");
-                    let decks = DECK_STATUS.read().expect("RwLock failed");
-                    for ftype in [ ("mp4", "video/mp4"), ("webm", "video/webm") ].iter() {
-                        match super::logic::get_deck_assoc_file(&deck_id, &decks, ftype.0) {
-                            None => continue,
-                            Some(data) => {
-                                return Response::from_data(ftype.1, data).with_no_cache()
-                            }
-                        }
-                    }
-                    Response::empty_404().with_no_cache()
+        (&Method::POST, ["deckLoaded", id]) => {
+            trace!("Deck load API call");
+            ingest(app, Ingest::DeckLoaded, Some(id.to_string()), request.json_body()?)?;
+            Ok(empty_204())
+        },
+
+        (&Method::POST, ["updateDeck", id]) => {
+            trace!("Deck update API call");
+            ingest(app, Ingest::UpdateDeck, Some(id.to_string()), request.json_body()?)?;
+            Ok(empty_204())
+        },
+
+        (&Method::POST, ["updateMasterClock"]) => {
+            trace!("Clock update API call");
+            ingest(app, Ingest::UpdateMasterClock, None, request.json_body()?)?;
+            Ok(empty_204())
+        },
+
+        (&Method::POST, ["updateChannel", id]) => {
+            trace!("Update channel API call");
+            ingest(app, Ingest::UpdateChannel, Some(id.to_string()), request.json_body()?)?;
+            Ok(empty_204())
+        },
+
+        (&Method::GET, ["status"]) => {
+            trace!("Status API call");
+            Ok(no_cache(json(&StatusResponse::create(app)?)?))
+        },
+
+        (&Method::GET, ["metrics"]) => {
+            trace!("Metrics API call");
+            let gauges = {
+                let state = app.state()?;
+                metrics::Gauges {
+                    ws_peers: ws_server::peer_count(app),
+                    master_bpm: state.clock.bpm,
+                    decks_on_air: state.songs_on_air(&app.settings().mixing).len(),
+                }
+            };
+            Ok(no_cache(data("text/plain; version=0.0.4", app.metrics.render(&gauges))))
+        },
+
+        (&Method::GET, ["nowPlaying"]) => {
+            trace!("Now playing info API call");
+            let state = app.state()?;
+            json(&NowPlayingResponse::create(&state, &app.settings().mixing))
+        },
+
+        (&Method::GET, ["override", deck_id]) => {
+            trace!("Override get over HTTP");
+            let overrides = app.state()?.overrides(deck_id).ok_or_else(|| RelayError::UnknownDeck(deck_id.to_string()))?;
+            Ok(no_cache(json(&overrides)?))
+        },
+
+        (&Method::POST, ["override", deck_id]) => {
+            trace!("Override set over HTTP");
+            let request: OverrideRequest = serde_json::from_value(request.json_body()?)
+                .map_err(|e| RelayError::BadRequest(e.to_string()))?;
+            Ok(no_cache(json(&set_override(app, deck_id, request)?)?))
+        },
+
+        (&Method::DELETE, ["override", deck_id]) => {
+            trace!("Override removal over HTTP");
+            let scope = match request.query_param("scope") {
+                Some(scope) => serde_json::from_value(serde_json::Value::String(scope))
+                    .map_err(|_| RelayError::BadRequest(String::from("scope must be deck or file")))?,
+                None => OverrideScope::default(),
+            };
+            Ok(no_cache(json(&set_override(app, deck_id, OverrideRequest::clear(scope))?)?))
+        },
+
+        (&Method::GET, ["mixHints"]) => {
+            trace!("Mix hints API call");
+            Ok(no_cache(json(&app.state()?.mix_hints(&app.settings().mixing))?))
+        },
+
+        (&Method::GET, ["stats", "track"]) => {
+            trace!("Track statistics API call");
+            let state = app.state()?;
+            let entry = match (request.query_param("path"), request.query_param("artist"), request.query_param("title")) {
+                (Some(path), _, _) => state.track_stats.get_key_value(&path).map(|(path, stats)| StatsEntry { path, stats }),
+                (None, Some(artist), Some(title)) => stats::find_by_name(&state.track_stats, &artist, &title),
+                _ => return Err(RelayError::BadRequest(String::from("either path or artist and title are required"))),
+            };
+            let entry = entry.ok_or_else(|| RelayError::NotFound(String::from("statistics of the track")))?;
+            Ok(no_cache(json(&entry)?))
+        },
+
+        (&Method::GET, ["stats", "top"]) => {
+            trace!("Top tracks API call");
+            let limit = match request.query_param("limit") {
+                Some(limit) => limit.parse().map_err(|_| RelayError::BadRequest(String::from("limit must be a number")))?,
+                None => 10,
+            };
+            let order: TopOrder = match request.query_param("by") {
+                Some(by) => serde_json::from_value(serde_json::Value::String(by))
+                    .map_err(|_| RelayError::BadRequest(String::from("by must be plays or airtime")))?,
+                None => TopOrder::default(),
+            };
+            let state = app.state()?;
+            Ok(no_cache(json(&stats::top(&state.track_stats, order, limit))?))
+        },
+
+        (&Method::GET, ["render"]) => {
+            trace!("Template render API call");
+            let template = request.query_param("template")
+                .ok_or_else(|| RelayError::BadRequest(String::from("template is required")))?;
+            Ok(no_cache(text(render_template(app, &template)?)))
+        },
+
+        (&Method::GET, ["render", name]) => {
+            trace!("Named template render API call");
+            Ok(no_cache(text(render_named(app, name)?)))
+        },
+
+        (&Method::GET, ["history"]) => {
+            trace!("History API call");
+            Ok(no_cache(json(&app.state()?.history(None))?))
+        },
+
+        (&Method::GET, ["artwork", deck_id]) => {
+            trace!("Artwork get over HTTP");
+            let state = app.state()?;
+            let art = deck_artwork(app, &state, deck_id)?;
+            Ok(no_cache(data(&art.mime_type, art.data)))
+        },
+
+        (&Method::GET, ["subtitles", deck_id]) => {
+            trace!("Subtitles get over HTTP");
+            let deck_id = deck_id.to_string();
+            let state = app.state()?;
+            let hidden = state.is_hidden(&deck_id, &app.settings().mixing);
+            match super::logic::get_deck_assoc_file(&deck_id, &state.decks, "ass").filter(|_| !hidden) {
+                None => {
+                    Err(RelayError::NotFound(format!("subtitles for deck {}", deck_id)))
                 },
+                Some(contents) => {
+                    Ok(no_cache(data("text/plain", contents)))
+                }
+            }
+        },
 
-                (GET) (/filename/{deck_id: Deck}) => {
-                    trace!("Get song filename without extension");
-                    let decks = DECK_STATUS.read().expect("RwLock failed");
-                    match super::logic::get_deck_assoc_filename(&deck_id, &decks) {
-                        None => {
-                            Response::empty_404().with_no_cache()
-                        },
-                        Some(text) => {
-                            return Response::text(text).with_no_cache()
-                        }
+        (&Method::GET, ["video", deck_id]) => {
+            trace!("Video get over HTTP");
+            let deck_id = deck_id.to_string();
+            let state = app.state()?;
+            if state.is_hidden(&deck_id, &app.settings().mixing) {
+                return Err(RelayError::NotFound(format!("video for deck {}", deck_id)));
+            }
+            for ftype in [ ("mp4", "video/mp4"), ("webm", "video/webm") ].iter() {
+                match super::logic::get_deck_assoc_file(&deck_id, &state.decks, ftype.0) {
+                    None => continue,
+                    Some(contents) => {
+                        return Ok(no_cache(data(ftype.1, contents)))
                     }
+                }
+            }
+            Err(RelayError::NotFound(format!("video for deck {}", deck_id)))
+        },
+
+        (&Method::GET, ["filename", deck_id]) => {
+            trace!("Get song filename without extension");
+            let deck_id = deck_id.to_string();
+            let state = app.state()?;
+            let hidden = state.is_hidden(&deck_id, &app.settings().mixing);
+            match super::logic::get_deck_assoc_filename(&deck_id, &state.decks).filter(|_| !hidden) {
+                None => {
+                    Err(RelayError::NotFound(format!("filename for deck {}", deck_id)))
                 },
+                Some(filename) => {
+                    Ok(no_cache(text(filename)))
+                }
+            }
+        },
+
+        _ => {
+            serve_asset(&app.settings().http.webroot, &request.path)
+        }
+    }
+}
+
+/// Read the request and run its handler on the blocking pool, since handlers lock the state and read files
+async fn serve(app: Arc<App>, request: Request<Body>) -> std::result::Result<Response<Body>, Infallible> {
+    let started_at = Instant::now();
+    let (parts, body) = request.into_parts();
+    let raw_url = parts.uri.to_string();
 
-                _ => {
-                    rouille::match_assets(&request, &root).with_no_cache()
+    let response = match hyper::body::to_bytes(body).await {
+        Err(e) => respond(|| Err(RelayError::BadRequest(format!("could not read the request body: {}", e)))),
+        Ok(body) => {
+            let request = HttpRequest {
+                method: parts.method.clone(),
+                path: parts.uri.path().to_string(),
+                query: parts.uri.query().unwrap_or_default().to_string(),
+                content_type: parts.headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).map(String::from),
+                body,
+            };
+            let handler_app = app.clone();
+            match tokio::task::spawn_blocking(move || respond(|| route(&handler_app, &request))).await {
+                Ok(response) => response,
+                Err(e) => {
+                    error!("Handler panicked: {} {}: {}", parts.method, raw_url, e);
+                    let mut response = Response::new(Body::empty());
+                    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                    return Ok(response);
                 }
-            )
-        })
+            }
+        }
+    };
+
+    let elapsed = started_at.elapsed();
+    info!("{} {}: rslt={} time={}", parts.method, raw_url, response.status().as_u16(), format_time(elapsed));
+    app.metrics.observe_latency(route_label(parts.uri.path()), elapsed);
+    Ok(response)
+}
+
+/// Bind the HTTP server and serve it on the current runtime until the relay shuts down.
+/// Returns the bound address and the server task.
+pub fn start(app: Arc<App>) -> Result<(SocketAddr, JoinHandle<()>)> {
+    let cfg = app.settings();
+    let host = &cfg.http.bind;
+    let port = &cfg.http.port;
+
+    let listener = std::net::TcpListener::bind(format!("{}:{}", host, port))?;
+    let addr = listener.local_addr()?;
+    let server_app = app.clone();
+    let make_service = make_service_fn(move |_conn| {
+        let app = server_app.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |request| serve(app.clone(), request))) }
+    });
+    let server = Server::from_tcp(listener)?
+        .serve(make_service)
+        .with_graceful_shutdown(async move { app.shutdown_requested().await });
+
+    info!("Start HTTP at {} in {}", addr, cfg.http.webroot);
+    let task = tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!("HTTP server failed: {}", e);
+        }
+        debug!("HTTP server stopped");
     });
+    Ok((addr, task))
 }
@@ -0,0 +1,38 @@
+#[macro_use]
+extern crate log;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate id3;
+extern crate metaflac;
+extern crate tokio;
+extern crate tokio_tungstenite;
+extern crate infer;
+extern crate regex;
+extern crate sha2;
+extern crate base64;
+
+pub mod api;
+pub mod app;
+pub mod cleanup;
+pub mod cli;
+mod commands;
+pub mod error;
+pub mod http_server;
+mod logic;
+mod metrics;
+pub mod mix_hints;
+pub mod obs;
+mod outputs;
+pub mod overrides;
+pub mod recorder;
+pub mod settings;
+pub mod simulator;
+pub mod state;
+pub mod stats;
+pub mod status;
+mod streams;
+pub mod template;
+mod throttle;
+mod ws_server;
@@ -1,18 +1,21 @@
 use super::{
     api::{channel::*, deck::*, Channel, Deck},
-    settings,
+    metrics::Metrics,
+    settings::MixingSettings,
 };
 use std::collections::HashMap;
 use std::path::Path;
 use std::fs::File;
 use std::io::Read;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 
 pub fn get_songs_on_air(
     cur_decks: &HashMap<Deck, DeckStatus>,
     cur_chans: &HashMap<Channel, ChannelStatus>,
+    setting: &MixingSettings,
 ) -> Vec<DeckStatus> {
     trace!("Get songs currently on air");
-    let setting = &settings::ServerSettings::shared().mixing;
     let deck_list = setting.deck_list.clone();
 
     let on_air_decks = deck_list.iter().filter(|&deck| {
@@ -37,67 +40,98 @@ pub fn get_songs_on_air(
     songs_on_air
 }
 
+#[derive(Clone)]
 pub struct Artwork {
     pub mime_type: String,
     pub data: Vec<u8>,
 }
 
-pub fn get_deck_artwork(deck_id: &Deck, decks: &HashMap<Deck, DeckStatus>) -> Option<Artwork> {
+/// Artwork of the files loaded into the decks, `None` for files without artwork
+pub type ArtworkCache = Mutex<HashMap<String, Option<Artwork>>>;
+
+pub fn get_deck_artwork(
+    deck_id: &Deck,
+    decks: &HashMap<Deck, DeckStatus>,
+    cache: &ArtworkCache,
+    metrics: &Metrics,
+) -> Option<Artwork> {
     if let Some(deck) = decks.get(deck_id) {
         let fpath = &deck.file_path;
         trace!("Get artwork of deck {}: {}", deck_id, fpath);
-        let file_path = Path::new(&fpath);
-        if !file_path.exists() {
-            error!("Deck {} is playing a nonexistent file {}", deck_id, file_path.display());
-            None
-        } else {
-            if let Some(extz) = file_path.extension() {
-                match extz.to_string_lossy().to_lowercase().as_str() {
-                    "flac" => {
-                        if let Ok(tags) = metaflac::Tag::read_from_path(file_path) {
-                            if let Some(pic) = tags.pictures().nth(0) {
-                                return Some(Artwork {
-                                    mime_type: pic.mime_type.clone(),
-                                    data: pic.data.clone(),
-                                });
-                            } else {
-                                error!("Could not find or read picture in FLAC file: {}", file_path.display());
-                            }
+        let mut cache = match cache.lock() {
+            Ok(cache) => cache,
+            Err(e) => {
+                error!("Artwork cache is unavailable: {}", e);
+                return read_artwork(deck_id, fpath);
+            }
+        };
+
+        // Forget artwork of files that are no longer loaded
+        cache.retain(|path, _| decks.values().any(|d| &d.file_path == path));
+        if let Some(art) = cache.get(fpath) {
+            metrics.count_artwork_cache(true);
+            return art.clone();
+        }
+
+        metrics.count_artwork_cache(false);
+        let art = read_artwork(deck_id, fpath);
+        cache.insert(fpath.clone(), art.clone());
+        art
+    } else {
+        error!("Could not get deck {}", deck_id);
+        None
+    }
+}
+
+fn read_artwork(deck_id: &Deck, fpath: &str) -> Option<Artwork> {
+    let file_path = Path::new(&fpath);
+    if !file_path.exists() {
+        error!("Deck {} is playing a nonexistent file {}", deck_id, file_path.display());
+        None
+    } else {
+        if let Some(extz) = file_path.extension() {
+            match extz.to_string_lossy().to_lowercase().as_str() {
+                "flac" => {
+                    if let Ok(tags) = metaflac::Tag::read_from_path(file_path) {
+                        if let Some(pic) = tags.pictures().next() {
+                            return Some(Artwork {
+                                mime_type: pic.mime_type.clone(),
+                                data: pic.data.clone(),
+                            });
                         } else {
-                            error!("Could not read metadata in FLAC file: {}", file_path.display());
+                            error!("Could not find or read picture in FLAC file: {}", file_path.display());
                         }
-
-                        None
+                    } else {
+                        error!("Could not read metadata in FLAC file: {}", file_path.display());
                     }
-                    "mp3" => {
-                        if let Ok(tags) = id3::Tag::read_from_path(file_path) {
-                            if let Some(pic) = tags.pictures().nth(0) {
-                                return Some(Artwork {
-                                    mime_type: pic.mime_type.clone(),
-                                    data: pic.data.clone(),
-                                });
-                            } else {
-                                error!("Could not find or read picture in MP3 file: {}", file_path.display());
-                            }
+
+                    None
+                }
+                "mp3" => {
+                    if let Ok(tags) = id3::Tag::read_from_path(file_path) {
+                        if let Some(pic) = tags.pictures().next() {
+                            return Some(Artwork {
+                                mime_type: pic.mime_type.clone(),
+                                data: pic.data.clone(),
+                            });
                         } else {
-                            error!("Could not read metadata in MP3 file: {}", file_path.display());
+                            error!("Could not find or read picture in MP3 file: {}", file_path.display());
                         }
-
-                        None
+                    } else {
+                        error!("Could not read metadata in MP3 file: {}", file_path.display());
                     }
-                    _ => {
-                        error!("Unsupported file extension to extract artwork from: {}", extz.to_string_lossy());
-                        None
-                    },
+
+                    None
                 }
-            } else {
-                error!("Could not determine extension of file {}", file_path.display());
-                None
+                _ => {
+                    error!("Unsupported file extension to extract artwork from: {}", extz.to_string_lossy());
+                    None
+                },
             }
+        } else {
+            error!("Could not determine extension of file {}", file_path.display());
+            None
         }
-    } else {
-        error!("Could not get deck {}", deck_id);
-        None
     }
 }
 
@@ -114,7 +148,7 @@ pub fn get_deck_assoc_file(deck_id: &Deck, decks: &HashMap<Deck, DeckStatus>, ex
             if subtitle_path.exists() {
                 if let Ok(mut handle) = File::open(&subtitle_path) {
                     let mut res: Vec<u8> = vec![];
-                    if let Ok(_) = handle.read_to_end(&mut res) {
+                    if handle.read_to_end(&mut res).is_ok() {
                         Some(res)
                     } else {
                         error!("Could not read {:?}", subtitle_path);
@@ -140,10 +174,10 @@ pub fn get_deck_assoc_filename(deck_id: &Deck, decks: &HashMap<Deck, DeckStatus>
         let fpath = &deck.file_path;
         trace!("Get associated filename of deck {}: {}", deck_id, fpath);
         let file_path = Path::new(&fpath);
-        let subtitle_filename = file_path.file_stem();
-        if subtitle_filename.is_some() {
-            debug!("Returning filename {}", subtitle_filename.unwrap().to_os_string().into_string().unwrap());
-            return Some(subtitle_filename.unwrap().to_os_string().into_string().unwrap());
+        if let Some(subtitle_filename) = file_path.file_stem() {
+            let filename = subtitle_filename.to_string_lossy().into_owned();
+            debug!("Returning filename {}", filename);
+            Some(filename)
         }
         else {
             debug!("Filename not found, returning None");
@@ -154,3 +188,20 @@ pub fn get_deck_assoc_filename(deck_id: &Deck, decks: &HashMap<Deck, DeckStatus>
         None
     }
 }
+
+/// Write the file through a temporary file next to it, so that a reader never sees it half-written
+pub fn write_atomic(path: &str, contents: &[u8]) -> std::io::Result<()> {
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+    if let Some(parent) = Path::new(path).parent() {
+        if !parent.as_os_str().is_empty() {
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+    // Unique, so that concurrent writes of the same file do not share a temporary file
+    let tmp_path = format!("{}.{}-{}.tmp", path, std::process::id(), WRITES.fetch_add(1, Ordering::Relaxed));
+    if let Err(e) = std::fs::write(&tmp_path, contents).and_then(|_| std::fs::rename(&tmp_path, path)) {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    Ok(())
+}
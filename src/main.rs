@@ -1,66 +1,128 @@
 #[macro_use]
-extern crate rouille;
-#[macro_use]
 extern crate log;
-extern crate serde;
-#[macro_use]
-extern crate serde_derive;
-extern crate serde_json;
-#[macro_use]
-extern crate lazy_static;
-extern crate id3;
-extern crate metaflac;
-extern crate tokio;
-extern crate tokio_tungstenite;
-extern crate infer;
 
-use std::{
-    collections::HashMap,
-    sync::{Arc, RwLock},
+use traktor_obs_relay::{
+    app::App,
+    cli, http_server, recorder,
+    settings::{ServerSettings, SettingsError, SettingsSource},
+    simulator,
 };
 
-mod api;
-mod http_server;
-mod logic;
-mod settings;
-mod ws_server;
-
-use api::{channel::*, deck::*, master_clock::*, Channel, Deck};
+/// Print the reasons the settings could not be used
+fn print_settings_error(e: &SettingsError) {
+    match e {
+        SettingsError::Invalid(problems) => {
+            eprintln!("Configuration has {} problem(s):", problems.len());
+            for problem in problems.iter() {
+                eprintln!("  - {}", problem);
+            }
+        }
+        SettingsError::Read(e) => eprintln!("Configuration failure: {}", e),
+    }
+}
 
-lazy_static! {
-    pub static ref DECK_STATUS: Arc<RwLock<HashMap<Deck, DeckStatus>>> =
-        Arc::new(RwLock::new(HashMap::new()));
-    pub static ref MASTER_CLOCK: Arc<RwLock<MasterClock>> = Arc::new(RwLock::new(MasterClock {
-        ..Default::default()
-    }));
-    pub static ref CHANNEL_STATUS: Arc<RwLock<HashMap<Channel, ChannelStatus>>> =
-        Arc::new(RwLock::new(HashMap::new()));
+/// Resolves when the process is asked to stop
+async fn stop_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(e) => error!("Could not listen for SIGTERM: {}", e),
+        }
+    }
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        error!("Could not listen for Ctrl-C: {}", e);
+        std::future::pending::<()>().await;
+    }
 }
 
-fn main() {
-    use std::str::FromStr;
-    let log_level = settings::ServerSettings::shared().log_level.clone().unwrap_or(String::from("Info"));
+#[tokio::main]
+async fn main() {
+    let args = cli::Args::from_env();
+    let source = SettingsSource::from_args(&args);
+
+    if args.check_config {
+        match ServerSettings::read_validated(&source) {
+            Ok(_) => println!("Configuration OK"),
+            Err(e) => {
+                print_settings_error(&e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if args.print_config {
+        match ServerSettings::read(&source).map(|s| s.to_toml()) {
+            Ok(Ok(text)) => print!("{}", text),
+            Ok(Err(e)) => {
+                eprintln!("Could not print configuration: {}", e);
+                std::process::exit(1);
+            }
+            Err(e) => {
+                print_settings_error(&SettingsError::Read(e));
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Logger lets everything through, the effective level is controlled by max_level so that it can be changed on reload
     simple_logger::SimpleLogger::new()
-        .with_level(log::LevelFilter::from_str(&log_level).unwrap_or(log::LevelFilter::Info))
+        .with_level(log::LevelFilter::Trace)
         .init()
         .unwrap();
+    log::set_max_level(log::LevelFilter::Info);
 
-    // preheat channel_status
-    {
-        let mut chan_status = CHANNEL_STATUS.write().unwrap();
-        let channel_map = &settings::ServerSettings::shared().mixing.deck_channel_map;
-        for (_, channel) in channel_map.iter() {
-            trace!("Preheat channel matrix data {}", channel);
-            chan_status.insert(*channel, ChannelStatus { is_on_air: true });
+    let settings = match ServerSettings::read_validated(&source) {
+        Ok(settings) => settings,
+        Err(e) => {
+            print_settings_error(&e);
+            std::process::exit(1);
         }
-        drop(chan_status);
-    }
+    };
+    log::set_max_level(settings.log_level_filter());
 
-    http_server::spawn_http();
-    ws_server::spawn_ws();
+    let app = App::new(settings, source);
+    if let Err(e) = app.start() {
+        error!("{}", e);
+        std::process::exit(1);
+    }
 
-    loop {
-        debug!("Freezing main thread for an eternity");
-        std::thread::sleep(std::time::Duration::from_secs(u64::MAX));
+    if let Some(path) = &args.record {
+        if let Err(e) = app.start_recording(path) {
+            error!("Could not start recording to {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(path) = &args.replay {
+        match recorder::spawn_replay(&app, path, args.speed.unwrap_or(1.0)) {
+            Ok(task) => app.spawn(task),
+            Err(e) => {
+                error!("Could not replay {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
     }
+    if let Some(path) = &args.simulate {
+        match simulator::spawn_simulation(&app, path) {
+            Ok(task) => app.spawn(task),
+            Err(e) => {
+                error!("Could not simulate {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    app.spawn(http_server::spawn_settings_watcher(app.clone()));
+
+    stop_signal().await;
+    app.shutdown().await;
 }
@@ -0,0 +1,143 @@
+use super::status::Ingest;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds of the handler latency histogram buckets, in seconds
+const LATENCY_BUCKETS: [f64; 10] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+/// Observations of a single handler latency
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (idx, bound) in LATENCY_BUCKETS.iter().enumerate() {
+            if value <= *bound {
+                self.buckets[idx] += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+/// Counters of a relay instance
+#[derive(Default)]
+pub struct Metrics {
+    ingest: [AtomicU64; 4],
+    ws_broadcasts: AtomicU64,
+    ws_bytes: AtomicU64,
+    ws_dropped: AtomicU64,
+    ws_reaped: AtomicU64,
+    artwork_cache_hits: AtomicU64,
+    artwork_cache_misses: AtomicU64,
+    latency: Mutex<BTreeMap<&'static str, Histogram>>,
+}
+
+impl Metrics {
+    /// Count a call made by Traktor
+    pub fn count_ingest(&self, ingest: Ingest) {
+        self.ingest[ingest as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a websocket broadcast
+    pub fn count_ws_broadcast(&self) {
+        self.ws_broadcasts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a text message of `bytes` length handed to a websocket client
+    pub fn count_ws_sent(&self, bytes: usize) {
+        self.ws_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Count a message that did not fit into a websocket client's queue
+    pub fn count_ws_dropped(&self) {
+        self.ws_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a websocket client disconnected for not answering or not keeping up
+    pub fn count_ws_reaped(&self) {
+        self.ws_reaped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an artwork lookup
+    pub fn count_artwork_cache(&self, hit: bool) {
+        if hit {
+            self.artwork_cache_hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.artwork_cache_misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Record how long an HTTP handler took
+    pub fn observe_latency(&self, route: &'static str, elapsed: Duration) {
+        match self.latency.lock() {
+            Ok(mut latency) => latency.entry(route).or_default().observe(elapsed.as_secs_f64()),
+            Err(e) => error!("Could not record latency of {}: {}", route, e),
+        }
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self, gauges: &Gauges) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP traktor_relay_ingest_total Calls made by Traktor, by route.\n");
+        out.push_str("# TYPE traktor_relay_ingest_total counter\n");
+        for ingest in [Ingest::DeckLoaded, Ingest::UpdateDeck, Ingest::UpdateChannel, Ingest::UpdateMasterClock].iter() {
+            let _ = writeln!(
+                out,
+                "traktor_relay_ingest_total{{route=\"{}\"}} {}",
+                ingest.route(),
+                self.ingest[*ingest as usize].load(Ordering::Relaxed)
+            );
+        }
+
+        counter(&mut out, "traktor_relay_ws_broadcasts_total", "Messages broadcast over the websocket.", self.ws_broadcasts.load(Ordering::Relaxed));
+        counter(&mut out, "traktor_relay_ws_sent_bytes_total", "Bytes of messages sent to websocket clients.", self.ws_bytes.load(Ordering::Relaxed));
+        counter(&mut out, "traktor_relay_ws_dropped_messages_total", "Messages not sent to websocket clients with a full queue.", self.ws_dropped.load(Ordering::Relaxed));
+        counter(&mut out, "traktor_relay_ws_reaped_peers_total", "Websocket clients disconnected for not answering or not keeping up.", self.ws_reaped.load(Ordering::Relaxed));
+        counter(&mut out, "traktor_relay_artwork_cache_hits_total", "Artwork requests served from the cache.", self.artwork_cache_hits.load(Ordering::Relaxed));
+        counter(&mut out, "traktor_relay_artwork_cache_misses_total", "Artwork requests that had to read the track file.", self.artwork_cache_misses.load(Ordering::Relaxed));
+
+        gauge(&mut out, "traktor_relay_ws_peers", "Connected websocket clients.", gauges.ws_peers as f64);
+        gauge(&mut out, "traktor_relay_master_bpm", "Master clock BPM.", f64::from(gauges.master_bpm));
+        gauge(&mut out, "traktor_relay_decks_on_air", "Decks that can be heard by the listeners.", gauges.decks_on_air as f64);
+
+        out.push_str("# HELP traktor_relay_http_request_duration_seconds Time taken by the HTTP handlers, by route.\n");
+        out.push_str("# TYPE traktor_relay_http_request_duration_seconds histogram\n");
+        if let Ok(latency) = self.latency.lock() {
+            for (route, histogram) in latency.iter() {
+                for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets.iter()) {
+                    let _ = writeln!(out, "traktor_relay_http_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}", route, bound, count);
+                }
+                let _ = writeln!(out, "traktor_relay_http_request_duration_seconds_bucket{{route=\"{}\",le=\"+Inf\"}} {}", route, histogram.count);
+                let _ = writeln!(out, "traktor_relay_http_request_duration_seconds_sum{{route=\"{}\"}} {}", route, histogram.sum);
+                let _ = writeln!(out, "traktor_relay_http_request_duration_seconds_count{{route=\"{}\"}} {}", route, histogram.count);
+            }
+        }
+
+        out
+    }
+}
+
+/// Current values for the metrics that are not counted but measured on request
+pub struct Gauges {
+    pub ws_peers: usize,
+    pub master_bpm: f32,
+    pub decks_on_air: usize,
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = write!(out, "# HELP {} {}\n# TYPE {} counter\n{} {}\n", name, help, name, name, value);
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: f64) {
+    let _ = write!(out, "# HELP {} {}\n# TYPE {} gauge\n{} {}\n", name, help, name, name, value);
+}
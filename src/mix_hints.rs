@@ -0,0 +1,59 @@
+use super::{
+    api::{
+        deck::DeckStatus,
+        key::KeyRelation,
+        Deck,
+    },
+    settings::MixingSettings,
+};
+use std::collections::HashMap;
+
+/// How well the tracks on two decks mix together
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MixHint {
+    /// Deck that is mixed from
+    pub from: Deck,
+    /// Deck that is mixed into
+    pub to: Deck,
+    /// Relation of the resulting keys, `None` if any of them is unknown
+    pub key_relation: Option<KeyRelation>,
+    /// Effective BPM of `to` minus that of `from`
+    pub bpm_difference: f32,
+    /// Tempo change of `to` in percent that matches the BPM of `from`, `None` if `to` has no BPM
+    pub pitch: Option<f32>,
+}
+
+impl MixHint {
+    fn between(from: &Deck, from_track: &DeckStatus, to: &Deck, to_track: &DeckStatus) -> Self {
+        let key_relation = match (from_track.resulting_musical_key(), to_track.resulting_musical_key()) {
+            (Some(from_key), Some(to_key)) => Some(from_key.relation(&to_key)),
+            _ => None,
+        };
+        let (from_bpm, to_bpm) = (from_track.effective_bpm(), to_track.effective_bpm());
+        let pitch = if to_bpm > 0.0 { Some((from_bpm / to_bpm - 1.0) * 100.0) } else { None };
+        Self {
+            from: from.clone(),
+            to: to.clone(),
+            key_relation,
+            bpm_difference: to_bpm - from_bpm,
+            pitch,
+        }
+    }
+}
+
+/// Hints for every pair of loaded decks, in the deck list order
+pub fn compute(decks: &HashMap<Deck, DeckStatus>, mixing: &MixingSettings) -> Vec<MixHint> {
+    let loaded: Vec<(&Deck, &DeckStatus)> = mixing
+        .deck_list
+        .iter()
+        .filter_map(|deck| decks.get(deck).map(|track| (deck, track)))
+        .collect();
+    let mut hints = vec![];
+    for (idx, (from, from_track)) in loaded.iter().enumerate() {
+        for (to, to_track) in loaded[idx + 1..].iter() {
+            hints.push(MixHint::between(from, from_track, to, to_track));
+        }
+    }
+    hints
+}
@@ -0,0 +1,444 @@
+use super::{
+    api::Deck,
+    app::App,
+    error::{RelayError, Result},
+    settings::ObsSettings,
+    template::{self, TemplateValues},
+};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+/// Version of the OBS WebSocket RPC the relay speaks
+const RPC_VERSION: u64 = 1;
+
+/// How long to wait for OBS to answer
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// OBS WebSocket message kinds, see the `op` field of the protocol
+mod op {
+    pub const HELLO: u64 = 0;
+    pub const IDENTIFY: u64 = 1;
+    pub const IDENTIFIED: u64 = 2;
+    pub const REQUEST: u64 = 6;
+    pub const REQUEST_RESPONSE: u64 = 7;
+}
+
+type ObsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Changes of the mix that OBS actions can be run on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ObsEvent {
+    /// The first track of the session has started playing on air
+    FirstTrack,
+    /// Another track has started playing on air, or taken over from the one that stopped
+    TrackChanged,
+    /// A single track is left playing on air
+    SoloStarted,
+    /// The single track playing on air is joined by another, or stops
+    SoloEnded,
+}
+
+/// Something for OBS to do, texts being templates rendered against the current track
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "action", rename_all = "camelCase")]
+pub enum ObsAction {
+    /// Switch the program scene
+    SwitchScene { scene: String },
+    /// Set the text of a text source
+    SetText { source: String, text: String },
+    /// Show or hide a source in a scene
+    SetSourceVisible {
+        scene: String,
+        source: String,
+        #[serde(default = "default_visible")]
+        visible: bool,
+    },
+    /// Insert a chapter marker into the recording
+    ChapterMarker {
+        #[serde(default = "default_chapter_name")]
+        name: String,
+    },
+}
+
+fn default_visible() -> bool {
+    true
+}
+
+fn default_chapter_name() -> String {
+    String::from("%artist% — %title%")
+}
+
+impl ObsAction {
+    /// Problem with the action, if any
+    pub fn validate(&self) -> Option<String> {
+        match self {
+            ObsAction::SwitchScene { scene } | ObsAction::SetSourceVisible { scene, .. } if scene.is_empty() => {
+                Some(String::from("scene is empty"))
+            }
+            ObsAction::SetText { source, .. } | ObsAction::SetSourceVisible { source, .. } if source.is_empty() => {
+                Some(String::from("source is empty"))
+            }
+            _ => None,
+        }
+    }
+
+    /// The action with its texts rendered
+    fn render(&self, values: &TemplateValues) -> Self {
+        match self {
+            ObsAction::SetText { source, text } => {
+                ObsAction::SetText { source: source.clone(), text: template::render(text, values) }
+            }
+            ObsAction::ChapterMarker { name } => ObsAction::ChapterMarker { name: template::render(name, values) },
+            _ => self.clone(),
+        }
+    }
+}
+
+/// An action to run whenever the event happens
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ObsRule {
+    pub on: ObsEvent,
+    #[serde(flatten)]
+    pub action: ObsAction,
+}
+
+/// What the rules look at in the mix
+#[derive(Debug)]
+struct MixSnapshot {
+    /// Deck and file of the track that started playing on air last
+    current: Option<(Deck, String)>,
+    /// Number of tracks playing on air
+    playing: usize,
+}
+
+/// The mix as the rules have last seen it
+#[derive(Debug, Default)]
+struct Observed {
+    /// Last track that was playing on air, kept while the mix is paused
+    track: Option<(Deck, String)>,
+    /// Number of tracks playing on air
+    playing: usize,
+}
+
+impl Observed {
+    /// Take the new snapshot, returning the events that lead to it
+    fn update(&mut self, snapshot: MixSnapshot) -> Vec<ObsEvent> {
+        let mut events = vec![];
+        if snapshot.current.is_some() && snapshot.current != self.track {
+            if self.track.is_none() {
+                events.push(ObsEvent::FirstTrack);
+            }
+            events.push(ObsEvent::TrackChanged);
+            self.track = snapshot.current;
+        }
+        match (self.playing == 1, snapshot.playing == 1) {
+            (false, true) => events.push(ObsEvent::SoloStarted),
+            (true, false) => events.push(ObsEvent::SoloEnded),
+            _ => {}
+        }
+        self.playing = snapshot.playing;
+        events
+    }
+}
+
+/// Connection to OBS and the actions waiting to be sent over it
+#[derive(Default)]
+pub struct ObsClient {
+    /// The mix as the rules have last seen it
+    observed: Mutex<Observed>,
+    /// Actions waiting to be run, with their texts rendered
+    queue: Mutex<VecDeque<ObsAction>>,
+    /// Wakes up the connection task when actions are queued
+    wake: Notify,
+    /// Tells the connection task to connect again with the new settings
+    reconnect: Notify,
+    /// Whether OBS is connected and identified
+    connected: AtomicBool,
+}
+
+/// Why a session with OBS ended without failing
+enum SessionEnd {
+    /// The relay is shutting down
+    Shutdown,
+    /// The connection settings have changed
+    Reconnect,
+}
+
+impl ObsClient {
+    /// Whether OBS is connected and identified
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// Connect again if the connection settings have changed on a settings reload
+    pub(crate) fn settings_changed(&self, old: &ObsSettings, new: &ObsSettings) {
+        if old.url != new.url || old.password != new.password {
+            self.reconnect.notify_one();
+        }
+    }
+
+    /// Mark the connection as up or down, forgetting the actions queued for the previous one
+    fn set_connected(&self, connected: bool) {
+        self.connected.store(connected, Ordering::Relaxed);
+        match self.queue.lock() {
+            Ok(mut queue) => queue.clear(),
+            Err(e) => e.into_inner().clear(),
+        }
+    }
+}
+
+/// Look at the mix after a change and queue the actions of the rules whose events have happened
+pub fn observe(app: &App) -> Result<()> {
+    let settings = app.settings();
+    let obs = &settings.obs;
+    if obs.url.is_empty() || obs.rules.is_empty() {
+        return Ok(());
+    }
+    // The mix is left unobserved while OBS is away, and caught up with once it is connected
+    if !app.obs.is_connected() {
+        return Ok(());
+    }
+
+    let (snapshot, values) = {
+        let state = app.state()?;
+        let track = state.current_track(&settings.mixing);
+        let current = track.as_ref().filter(|track| track.is_playing).and_then(|track| track.deck.clone()).map(|deck| {
+            // File of the track as loaded, before the overrides could hide it
+            let path = state.decks.get(&deck).map(|loaded| loaded.file_path.clone()).unwrap_or_default();
+            (deck, path)
+        });
+        let playing = state.songs_on_air(&settings.mixing).iter().filter(|track| track.is_playing).count();
+        let values = template::values(track.as_ref(), &state.clock, settings.http.bpm_precision);
+        (MixSnapshot { current, playing }, values)
+    };
+
+    let events = app.obs.observed.lock()?.update(snapshot);
+    if events.is_empty() {
+        return Ok(());
+    }
+    let mut queue = app.obs.queue.lock()?;
+    for event in events {
+        debug!("OBS event {:?}", event);
+        for rule in obs.rules.iter().filter(|rule| rule.on == event) {
+            queue.push_back(rule.action.render(&values));
+        }
+    }
+    app.obs.wake.notify_one();
+    Ok(())
+}
+
+/// Answer to the authentication challenge of OBS
+pub fn authentication(password: &str, salt: &str, challenge: &str) -> String {
+    let secret = base64::encode(Sha256::digest(format!("{}{}", password, salt).as_bytes()));
+    base64::encode(Sha256::digest(format!("{}{}", secret, challenge).as_bytes()))
+}
+
+/// An identified connection to OBS
+struct Session {
+    ws: ObsStream,
+    next_id: u64,
+}
+
+impl Session {
+    /// Connect to OBS and identify, answering the authentication challenge if there is one
+    async fn connect(settings: &ObsSettings) -> Result<Self> {
+        let (ws, _) = tokio_tungstenite::connect_async(settings.url.as_str()).await?;
+        let mut session = Session { ws, next_id: 0 };
+
+        let hello = session.receive(op::HELLO).await?;
+        let mut identify = json!({ "rpcVersion": RPC_VERSION, "eventSubscriptions": 0 });
+        if let Some(auth) = hello.get("authentication") {
+            let field = |name: &str| auth.get(name).and_then(Value::as_str).unwrap_or_default().to_string();
+            identify["authentication"] = json!(authentication(&settings.password, &field("salt"), &field("challenge")));
+        }
+        session.send(op::IDENTIFY, identify).await?;
+        session.receive(op::IDENTIFIED).await?;
+        Ok(session)
+    }
+
+    async fn send(&mut self, op: u64, data: Value) -> Result<()> {
+        let msg = json!({ "op": op, "d": data });
+        trace!("To OBS: {}", msg);
+        self.ws.send(Message::Text(msg.to_string())).await?;
+        Ok(())
+    }
+
+    /// Wait for the next message of the kind, skipping the others
+    async fn receive(&mut self, expected: u64) -> Result<Value> {
+        loop {
+            let received = tokio::time::timeout(RESPONSE_TIMEOUT, self.ws.next())
+                .await
+                .map_err(|_| RelayError::Obs(String::from("OBS did not answer in time")))?;
+            match received {
+                None => return Err(tungstenite::Error::ConnectionClosed.into()),
+                Some(msg) => {
+                    if let Some(data) = Self::parse(msg?, expected)? {
+                        return Ok(data);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Data of the message if it is of the expected kind
+    fn parse(msg: Message, expected: u64) -> Result<Option<Value>> {
+        match msg {
+            Message::Text(text) => {
+                trace!("From OBS: {}", text);
+                let mut msg: Value = serde_json::from_str(&text)?;
+                if msg.get("op").and_then(Value::as_u64) == Some(expected) {
+                    Ok(Some(msg["d"].take()))
+                } else {
+                    Ok(None)
+                }
+            }
+            Message::Close(frame) => {
+                let reason = frame.map(|frame| format!("{} ({})", frame.reason, u16::from(frame.code))).unwrap_or_default();
+                Err(RelayError::Obs(format!("OBS closed the connection {}", reason)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Make a request, returning its response data
+    async fn request(&mut self, request_type: &str, data: Value) -> Result<Value> {
+        self.next_id += 1;
+        let id = self.next_id.to_string();
+        self.send(op::REQUEST, json!({ "requestType": request_type, "requestId": id, "requestData": data })).await?;
+        loop {
+            let mut response = self.receive(op::REQUEST_RESPONSE).await?;
+            if response["requestId"] != json!(id) {
+                continue;
+            }
+            let status = &response["requestStatus"];
+            if status["result"] != json!(true) {
+                let comment = status["comment"].as_str().map(String::from).unwrap_or_else(|| format!("code {}", status["code"]));
+                return Err(RelayError::Obs(format!("{} failed: {}", request_type, comment)));
+            }
+            return Ok(response["responseData"].take());
+        }
+    }
+
+    /// Run the action through the requests it takes
+    async fn run(&mut self, action: &ObsAction) -> Result<()> {
+        match action {
+            ObsAction::SwitchScene { scene } => {
+                self.request("SetCurrentProgramScene", json!({ "sceneName": scene })).await?;
+            }
+            ObsAction::SetText { source, text } => {
+                self.request("SetInputSettings", json!({ "inputName": source, "inputSettings": { "text": text } }))
+                    .await?;
+            }
+            ObsAction::SetSourceVisible { scene, source, visible } => {
+                let item = self.request("GetSceneItemId", json!({ "sceneName": scene, "sourceName": source })).await?;
+                let item_id = item["sceneItemId"]
+                    .as_i64()
+                    .ok_or_else(|| RelayError::Obs(format!("no scene item id for {} in {}", source, scene)))?;
+                let data = json!({ "sceneName": scene, "sceneItemId": item_id, "sceneItemEnabled": visible });
+                self.request("SetSceneItemEnabled", data).await?;
+            }
+            ObsAction::ChapterMarker { name } => {
+                self.request("CreateRecordChapter", json!({ "chapterName": name })).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the queued actions until the connection fails, its settings change or the relay shuts down
+    async fn serve(&mut self, app: &App) -> Result<SessionEnd> {
+        loop {
+            tokio::select! {
+                received = self.ws.next() => match received {
+                    None => return Err(tungstenite::Error::ConnectionClosed.into()),
+                    // Events are not subscribed to, anything else is a late response
+                    Some(msg) => { Self::parse(msg?, op::REQUEST_RESPONSE)?; }
+                },
+                _ = app.obs.wake.notified() => loop {
+                    let action = app.obs.queue.lock()?.pop_front();
+                    let action = match action {
+                        Some(action) => action,
+                        None => break,
+                    };
+                    match self.run(&action).await {
+                        Ok(()) => debug!("OBS has run {:?}", action),
+                        // OBS refused, the next actions can still work
+                        Err(RelayError::Obs(msg)) => warn!("OBS could not run {:?}: {}", action, msg),
+                        Err(e) => return Err(e),
+                    }
+                },
+                _ = app.obs.reconnect.notified() => {
+                    let _ = self.ws.close(None).await;
+                    return Ok(SessionEnd::Reconnect);
+                },
+                _ = app.shutdown_requested() => {
+                    let _ = self.ws.close(None).await;
+                    return Ok(SessionEnd::Shutdown);
+                },
+            }
+        }
+    }
+}
+
+/// Keep connected to OBS and run the queued actions, until the relay shuts down
+pub fn spawn(app: Arc<App>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        // Only the first of a series of failed attempts is worth a warning
+        let mut failing = false;
+        loop {
+            let settings = app.settings();
+            let obs = &settings.obs;
+            if !obs.url.is_empty() {
+                let connected = tokio::select! {
+                    connected = Session::connect(obs) => connected,
+                    _ = app.shutdown_requested() => break,
+                };
+                match connected {
+                    Ok(mut session) => {
+                        info!("Connected to OBS at {}", obs.url);
+                        failing = false;
+                        app.obs.set_connected(true);
+                        // Catch up with what happened in the mix while OBS was away
+                        let observe_app = app.clone();
+                        match tokio::task::spawn_blocking(move || observe(&observe_app)).await {
+                            Ok(Ok(())) => {}
+                            Ok(Err(e)) => error!("Could not look at the mix for OBS: {}", e),
+                            Err(e) => error!("Looking at the mix for OBS panicked: {}", e),
+                        }
+                        let served = session.serve(&app).await;
+                        app.obs.set_connected(false);
+                        match served {
+                            Ok(SessionEnd::Shutdown) => break,
+                            Ok(SessionEnd::Reconnect) => {
+                                info!("OBS settings have changed, connecting again");
+                                continue;
+                            }
+                            Err(e) => warn!("Lost the connection to OBS: {}", e),
+                        }
+                    }
+                    Err(e) if failing => debug!("Could not connect to OBS at {}: {}", obs.url, e),
+                    Err(e) => {
+                        warn!("Could not connect to OBS at {}: {}", obs.url, e);
+                        failing = true;
+                    }
+                }
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(obs.reconnect_interval)) => {},
+                _ = app.obs.reconnect.notified() => {},
+                _ = app.shutdown_requested() => break,
+            }
+        }
+        debug!("OBS client stopped");
+    })
+}
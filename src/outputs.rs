@@ -0,0 +1,86 @@
+use super::{app::App, error::Result, http_server, logic, template};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+/// Files kept up to date for the OBS text and image sources
+#[derive(Default)]
+pub struct FileOutputs {
+    /// Wakes up the output task when the mix has changed
+    wake: Notify,
+    /// What was last written to each file, so that unchanged files are not written again
+    written: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl FileOutputs {
+    /// Ask for the files to be brought up to date
+    pub fn refresh(&self) {
+        self.wake.notify_one();
+    }
+}
+
+/// Render the text files and copy the artwork of the current track
+fn update(app: &App) -> Result<()> {
+    let settings = app.settings();
+    let outputs = &settings.outputs;
+    if outputs.files.is_empty() && outputs.artwork.is_empty() {
+        return Ok(());
+    }
+
+    let mut files: Vec<(&str, Vec<u8>)> = vec![];
+    {
+        let state = app.state()?;
+        let track = state.current_track(&settings.mixing);
+        let values = template::values(track.as_ref(), &state.clock, settings.http.bpm_precision);
+        for (path, template) in outputs.files.iter() {
+            files.push((path, template::render(template, &values).into_bytes()));
+        }
+        if !outputs.artwork.is_empty() {
+            let deck = track.as_ref().and_then(|track| track.deck.as_deref());
+            let art = match deck {
+                Some(deck) => http_server::deck_artwork(app, &state, deck),
+                None => http_server::default_artwork(&settings.mixing, "-"),
+            };
+            match art {
+                Ok(art) => files.push((&outputs.artwork, art.data)),
+                Err(e) => warn!("No artwork to copy to {}: {}", outputs.artwork, e),
+            }
+        }
+    }
+
+    let mut written = app.outputs.written.lock()?;
+    for (path, contents) in files {
+        if written.get(path) == Some(&contents) {
+            continue;
+        }
+        trace!("Writing output file {}", path);
+        match logic::write_atomic(path, &contents) {
+            Ok(()) => {
+                written.insert(path.to_string(), contents);
+            }
+            Err(e) => error!("Could not write output file {}: {}", path, e),
+        }
+    }
+    Ok(())
+}
+
+/// Bring the output files up to date whenever the mix changes, until the relay shuts down
+pub fn spawn(app: Arc<App>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = app.outputs.wake.notified() => {
+                    let task_app = app.clone();
+                    match tokio::task::spawn_blocking(move || update(&task_app)).await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => error!("Could not update the output files: {}", e),
+                        Err(e) => error!("Output files update panicked: {}", e),
+                    }
+                },
+                _ = app.shutdown_requested() => break,
+            }
+        }
+        debug!("File outputs stopped");
+    })
+}
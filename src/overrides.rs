@@ -0,0 +1,178 @@
+use super::{
+    api::deck::{DeckStatus, TrackField},
+    error::Result,
+    settings::MixingSettings,
+};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Metadata to show instead of what Traktor has sent, fields left unset keep the original values
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct MetadataOverride {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub genre: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment2: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remixer: Option<String>,
+    /// Show the track as an ID, or reveal it in spite of the ID marker if `false`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hidden: Option<bool>,
+}
+
+impl MetadataOverride {
+    /// Whether the override does not change anything
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Replace the metadata of the track with the values set in the override
+    pub fn apply(&self, track: &mut DeckStatus) {
+        let fields = [
+            (&self.title, &mut track.title),
+            (&self.artist, &mut track.artist),
+            (&self.album, &mut track.album),
+            (&self.genre, &mut track.genre),
+            (&self.comment, &mut track.comment),
+            (&self.comment2, &mut track.comment2),
+            (&self.label, &mut track.label),
+            (&self.mix, &mut track.mix),
+            (&self.remixer, &mut track.remixer),
+        ];
+        for (value, field) in IntoIterator::into_iter(fields) {
+            if let Some(value) = value {
+                field.clone_from(value);
+            }
+        }
+    }
+}
+
+/// Whether the comments of the track carry the ID marker
+pub fn has_id_marker(track: &DeckStatus, mixing: &MixingSettings) -> bool {
+    let marker = mixing.id_marker.as_str();
+    // Comments as they were before the cleanup, in case a rule has removed the marker
+    let original_comments = track
+        .original
+        .iter()
+        .filter(|(field, _)| matches!(field, TrackField::Comment | TrackField::Comment2))
+        .map(|(_, value)| value);
+    let mut comments = IntoIterator::into_iter([&track.comment, &track.comment2]).chain(original_comments);
+    !marker.is_empty() && comments.any(|comment| comment.contains(marker))
+}
+
+/// Replace the metadata of the track with the ID placeholders, leaving nothing that could reveal it
+pub fn hide(track: &mut DeckStatus, mixing: &MixingSettings) {
+    track.title.clone_from(&mixing.id_title);
+    track.artist.clone_from(&mixing.id_artist);
+    for field in IntoIterator::into_iter([
+        &mut track.file_path,
+        &mut track.album,
+        &mut track.genre,
+        &mut track.comment,
+        &mut track.comment2,
+        &mut track.label,
+        &mut track.mix,
+        &mut track.remixer,
+        &mut track.featuring,
+    ]) {
+        field.clear();
+    }
+    track.original.clear();
+    track.stats = None;
+    track.is_hidden = true;
+}
+
+/// What an override applies to
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum OverrideScope {
+    /// The track currently loaded into the deck, forgotten when another track is loaded
+    #[default]
+    Deck,
+    /// The track file, whenever it is loaded, kept in the overrides file
+    File,
+}
+
+/// Request to change the override of a deck, or to remove it with `clear`
+#[derive(Deserialize, Debug)]
+#[serde(try_from = "Map<String, Value>")]
+pub struct OverrideRequest {
+    pub scope: OverrideScope,
+    /// Remove the override rather than setting it
+    pub clear: bool,
+    pub metadata: MetadataOverride,
+}
+
+impl OverrideRequest {
+    /// Request removing the override of the scope
+    pub fn clear(scope: OverrideScope) -> Self {
+        Self { scope, clear: true, metadata: MetadataOverride::default() }
+    }
+}
+
+// Not derived, as `deny_unknown_fields` does not work through `flatten`
+impl TryFrom<Map<String, Value>> for OverrideRequest {
+    type Error = String;
+
+    fn try_from(mut fields: Map<String, Value>) -> std::result::Result<Self, Self::Error> {
+        let scope = match fields.remove("scope") {
+            Some(scope) => serde_json::from_value(scope).map_err(|e| format!("scope: {}", e))?,
+            None => OverrideScope::default(),
+        };
+        let clear = match fields.remove("clear") {
+            Some(clear) => serde_json::from_value(clear).map_err(|e| format!("clear: {}", e))?,
+            None => false,
+        };
+        let metadata: MetadataOverride = serde_json::from_value(Value::Object(fields)).map_err(|e| e.to_string())?;
+        match (clear, metadata.is_empty()) {
+            (true, false) => Err(String::from("clear cannot be combined with metadata fields")),
+            (false, true) => Err(String::from("no metadata fields to override, use clear to remove the override")),
+            _ => Ok(Self { scope, clear, metadata }),
+        }
+    }
+}
+
+/// Overrides in effect for a deck
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DeckOverrides {
+    /// Override of the current load
+    pub deck: Option<MetadataOverride>,
+    /// Override of the loaded file
+    pub file: Option<MetadataOverride>,
+}
+
+/// Read the file overrides, keyed by track file path
+pub fn load(path: &str) -> Result<HashMap<String, MetadataOverride>> {
+    if !Path::new(path).exists() {
+        debug!("No overrides file at {}", path);
+        return Ok(HashMap::new());
+    }
+    let overrides: HashMap<String, MetadataOverride> = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    info!("Loaded {} track override(s) from {}", overrides.len(), path);
+    Ok(overrides)
+}
+
+/// Write the file overrides
+pub fn save(path: &str, overrides: &HashMap<String, MetadataOverride>) -> Result<()> {
+    super::logic::write_atomic(path, &serde_json::to_vec_pretty(overrides)?)?;
+    debug!("Saved {} track override(s) to {}", overrides.len(), path);
+    Ok(())
+}
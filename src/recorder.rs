@@ -0,0 +1,111 @@
+use super::{app::App, error::Result, http_server, status::Ingest};
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// A call made by Traktor, as stored in the session recording
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedCall {
+    /// Seconds since the recording was started
+    pub at: f64,
+    /// Route name of the call
+    pub route: String,
+    /// Deck or channel in the route if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// JSON body of the call
+    pub body: serde_json::Value,
+}
+
+/// Writer of a session recording
+pub struct Recorder {
+    started_at: Instant,
+    out: LineWriter<File>,
+}
+
+impl Recorder {
+    /// Start a recording in the file at `path`
+    pub fn create(path: &str) -> Result<Self> {
+        let file = File::create(path)?;
+        info!("Recording Traktor session to {}", path);
+        Ok(Self {
+            started_at: Instant::now(),
+            out: LineWriter::new(file),
+        })
+    }
+
+    /// Write the call to the recording
+    pub fn record(&mut self, kind: Ingest, id: Option<&str>, body: &serde_json::Value) {
+        let call = RecordedCall {
+            at: self.started_at.elapsed().as_secs_f64(),
+            route: kind.route().to_string(),
+            id: id.map(String::from),
+            body: body.clone(),
+        };
+        let written = serde_json::to_string(&call)
+            .map_err(|e| e.to_string())
+            .and_then(|line| writeln!(self.out, "{}", line).map_err(|e| e.to_string()));
+        if let Err(e) = written {
+            error!("Could not record {} call: {}", call.route, e);
+        }
+    }
+
+    /// Make sure everything recorded so far is written to the file
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.out.flush()?)
+    }
+}
+
+/// Read a recording and feed the calls to the relay with the original timing scaled by `speed`,
+/// until the recording ends or the relay shuts down
+pub fn spawn_replay(app: &Arc<App>, path: &str, speed: f64) -> Result<JoinHandle<()>> {
+    let contents = std::fs::read_to_string(path)?;
+    let path = path.to_string();
+    let app = app.clone();
+    Ok(tokio::spawn(async move {
+        info!("Replaying Traktor session from {} at {}x speed", path, speed);
+        let mut last_at = 0.0;
+        for (idx, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let call: RecordedCall = match serde_json::from_str(line) {
+                Ok(call) => call,
+                Err(e) => {
+                    error!("Skipping line {} of {}: {}", idx + 1, path, e);
+                    continue;
+                }
+            };
+            let kind = match Ingest::from_route(&call.route) {
+                Some(kind) => kind,
+                None => {
+                    error!("Skipping line {} of {}: unknown route {}", idx + 1, path, call.route);
+                    continue;
+                }
+            };
+
+            let delay = (call.at - last_at).max(0.0) / speed;
+            last_at = call.at;
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs_f64(delay)) => {}
+                _ = app.shutdown_requested() => {
+                    info!("Replay of {} stopped", path);
+                    return;
+                }
+            }
+
+            trace!("Replay {} call at {:.3}s", call.route, call.at);
+            let RecordedCall { at, route, id, body } = call;
+            let replay_app = app.clone();
+            match tokio::task::spawn_blocking(move || http_server::ingest(&replay_app, kind, id, body)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => error!("Replayed {} call at {:.3}s failed: {}", route, at, e),
+                Err(e) => error!("Replayed {} call at {:.3}s panicked: {}", route, at, e),
+            }
+        }
+        info!("Replay of {} finished", path);
+    }))
+}
@@ -1,10 +1,19 @@
-use super::api::{Channel, Deck};
+use super::{
+    api::{Channel, Deck},
+    cleanup::CleanupRule,
+    obs::ObsRule,
+};
 use config::{Config, ConfigError, File};
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Default location of the settings file
+const CONFIG_PATH: &str = "config.toml";
 
 /// HTTP part settings
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct HttpSettings {
     /// IP to bind to
     pub bind: String,
@@ -15,11 +24,72 @@ pub struct HttpSettings {
     /// Webroot to throw unmatched requests at
     pub webroot: String,
     /// Send verbose events to websocket or not
-    pub more_events: bool
+    pub more_events: bool,
+    /// Seconds between pings sent to websocket clients
+    #[serde(default = "HttpSettings::default_ws_ping_interval")]
+    pub ws_ping_interval: u64,
+    /// Seconds without hearing from a websocket client after which it is disconnected
+    #[serde(default = "HttpSettings::default_ws_pong_timeout")]
+    pub ws_pong_timeout: u64,
+    /// Messages that can wait to be sent to a single websocket client
+    #[serde(default = "HttpSettings::default_ws_queue_size")]
+    pub ws_queue_size: usize,
+    /// What to do with a websocket client whose queue is full
+    #[serde(default)]
+    pub ws_slow_client: SlowClientPolicy,
+    /// Decimals the master clock BPM is rounded to in the websocket messages
+    #[serde(default = "HttpSettings::default_bpm_precision")]
+    pub bpm_precision: u32,
+    /// Smallest change of the rounded master clock BPM that is pushed to the websocket clients
+    #[serde(default)]
+    pub bpm_min_delta: f32,
+    /// Milliseconds between master clock pushes at most
+    #[serde(default = "HttpSettings::default_bpm_rate_limit")]
+    pub bpm_rate_limit: u64,
+    /// Milliseconds without master clock changes after which the BPM is reported as settled
+    #[serde(default = "HttpSettings::default_bpm_settle_time")]
+    pub bpm_settle_time: u64,
+}
+
+impl HttpSettings {
+    fn default_ws_ping_interval() -> u64 {
+        10
+    }
+
+    fn default_ws_pong_timeout() -> u64 {
+        30
+    }
+
+    fn default_ws_queue_size() -> usize {
+        256
+    }
+
+    fn default_bpm_precision() -> u32 {
+        2
+    }
+
+    fn default_bpm_rate_limit() -> u64 {
+        250
+    }
+
+    fn default_bpm_settle_time() -> u64 {
+        1000
+    }
+}
+
+/// Handling of websocket clients that do not keep up with the messages
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlowClientPolicy {
+    /// Skip the messages that do not fit into the client's queue
+    #[default]
+    Drop,
+    /// Close the connection, so that the client can reconnect and catch up
+    Disconnect,
 }
 
 /// Logic part settings
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct MixingSettings {
     /// List of decks to consider for Now Playing
     pub deck_list: Vec<Deck>,
@@ -27,37 +97,476 @@ pub struct MixingSettings {
     pub deck_channel_map: HashMap<Deck, Channel>,
     /// Default cover art image path
     pub default_cover: String,
+    /// Seconds without any calls from Traktor after which it is considered disconnected
+    #[serde(default = "MixingSettings::default_traktor_timeout")]
+    pub traktor_timeout: u64,
+    /// File to keep the metadata overrides of the tracks in
+    #[serde(default = "MixingSettings::default_overrides_file")]
+    pub overrides_file: String,
+    /// Text in the track comment that marks it as an ID, nothing is marked if empty
+    #[serde(default = "MixingSettings::default_id_marker")]
+    pub id_marker: String,
+    /// File to keep the track statistics in, they are not kept if empty
+    #[serde(default = "MixingSettings::default_stats_file")]
+    pub stats_file: String,
+    /// Seconds between saves of the track statistics while they change
+    #[serde(default = "MixingSettings::default_stats_save_interval")]
+    pub stats_save_interval: u64,
+    /// Difference from the master clock BPM up to which a deck counts as matching it
+    #[serde(default = "MixingSettings::default_bpm_tolerance")]
+    pub bpm_tolerance: f32,
+    /// Title shown instead of the title of an ID
+    #[serde(default = "MixingSettings::default_id_placeholder")]
+    pub id_title: String,
+    /// Artist shown instead of the artist of an ID
+    #[serde(default = "MixingSettings::default_id_placeholder")]
+    pub id_artist: String,
+}
+
+impl MixingSettings {
+    fn default_traktor_timeout() -> u64 {
+        30
+    }
+
+    fn default_overrides_file() -> String {
+        String::from("./overrides.json")
+    }
+
+    fn default_stats_file() -> String {
+        String::from("./stats.json")
+    }
+
+    fn default_stats_save_interval() -> u64 {
+        30
+    }
+
+    fn default_bpm_tolerance() -> f32 {
+        0.1
+    }
+
+    fn default_id_marker() -> String {
+        String::from("[ID]")
+    }
+
+    fn default_id_placeholder() -> String {
+        String::from("ID")
+    }
+}
+
+/// Files written for the OBS text and image sources
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct OutputSettings {
+    /// Templates of the text files, by file path
+    #[serde(default)]
+    pub files: HashMap<String, String>,
+    /// Where to copy the artwork of the current track, nowhere if empty
+    #[serde(default)]
+    pub artwork: String,
+    /// Streaming servers to send the song title of the current track to
+    #[serde(default)]
+    pub streams: Vec<StreamSettings>,
+}
+
+/// Kind of streaming server, they each take the song title in their own way
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamServer {
+    Icecast,
+    Shoutcast1,
+    Shoutcast2,
+}
+
+/// A streaming server whose song title is kept up to date
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StreamSettings {
+    pub server: StreamServer,
+    /// Base URL of the server, such as `http://127.0.0.1:8000`
+    pub url: String,
+    /// Mount point of the stream, Icecast only
+    #[serde(default)]
+    pub mount: String,
+    /// Stream number, SHOUTcast v2 only
+    #[serde(default = "StreamSettings::default_sid")]
+    pub sid: u32,
+    /// User to log in as, Icecast only
+    #[serde(default = "StreamSettings::default_user")]
+    pub user: String,
+    /// Source or admin password
+    #[serde(default)]
+    pub password: String,
+    /// Template of the song title
+    #[serde(default = "StreamSettings::default_song")]
+    pub song: String,
+    /// Attempts to make again after a failed update
+    #[serde(default = "StreamSettings::default_retries")]
+    pub retries: u32,
+    /// Milliseconds between the attempts
+    #[serde(default = "StreamSettings::default_retry_delay")]
+    pub retry_delay: u64,
+}
+
+impl StreamSettings {
+    fn default_sid() -> u32 {
+        1
+    }
+
+    fn default_user() -> String {
+        String::from("source")
+    }
+
+    fn default_song() -> String {
+        String::from("%artist% - %title%")
+    }
+
+    fn default_retries() -> u32 {
+        3
+    }
+
+    fn default_retry_delay() -> u64 {
+        2000
+    }
+}
+
+/// Connection to OBS WebSocket and the actions run on the mix events
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ObsSettings {
+    /// OBS WebSocket (v5) server URL, such as `ws://127.0.0.1:4455` or `wss://` behind TLS, not connecting if empty
+    #[serde(default)]
+    pub url: String,
+    /// OBS WebSocket server password, if authentication is enabled
+    #[serde(default)]
+    pub password: String,
+    /// Seconds between connection attempts
+    #[serde(default = "ObsSettings::default_reconnect_interval")]
+    pub reconnect_interval: u64,
+    /// Actions to run on the mix events, in order
+    #[serde(default)]
+    pub rules: Vec<ObsRule>,
+}
+
+impl ObsSettings {
+    fn default_reconnect_interval() -> u64 {
+        5
+    }
+}
+
+impl Default for ObsSettings {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            password: String::new(),
+            reconnect_interval: Self::default_reconnect_interval(),
+            rules: vec![],
+        }
+    }
 }
 
 /// Common settings
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct ServerSettings {
     pub http: HttpSettings,
     pub mixing: MixingSettings,
-    pub log_level: Option<String>
+    pub log_level: Option<String>,
+    /// Metadata cleanup rules, run in order on every loaded track
+    #[serde(default)]
+    pub cleanup: Vec<CleanupRule>,
+    /// Files kept up to date for the OBS sources
+    #[serde(default)]
+    pub outputs: OutputSettings,
+    /// Templates served by `/render/<name>`, by name
+    #[serde(default)]
+    pub templates: HashMap<String, String>,
+    /// OBS WebSocket client
+    #[serde(default)]
+    pub obs: ObsSettings,
+}
+
+/// Outcome of a settings reload
+#[derive(Debug, Default)]
+pub struct ReloadReport {
+    /// Settings that were changed in the file but need a restart to take effect
+    pub restart_required: Vec<String>,
+}
+
+/// Where the settings come from: a file and values overriding it
+#[derive(Debug, Clone)]
+pub struct SettingsSource {
+    /// Settings file path
+    pub path: String,
+    /// Setting keys with values to apply on top of the file
+    pub overrides: Vec<(String, config::Value)>,
+}
+
+impl Default for SettingsSource {
+    fn default() -> Self {
+        Self {
+            path: String::from(CONFIG_PATH),
+            overrides: vec![],
+        }
+    }
+}
+
+impl SettingsSource {
+    /// Build the settings source from the command line arguments
+    pub fn from_args(args: &crate::cli::Args) -> Self {
+        let mut rslt = Self::default();
+        if let Some(path) = &args.config {
+            rslt.path = path.clone();
+        }
+        if let Some(bind) = &args.bind {
+            rslt.overrides.push((String::from("http.bind"), bind.clone().into()));
+        }
+        if let Some(port) = args.port {
+            rslt.overrides.push((String::from("http.port"), i64::from(port).into()));
+        }
+        if let Some(ws_port) = args.ws_port {
+            rslt.overrides.push((String::from("http.ws_port"), i64::from(ws_port).into()));
+        }
+        if let Some(webroot) = &args.webroot {
+            rslt.overrides.push((String::from("http.webroot"), webroot.clone().into()));
+        }
+        if let Some(log_level) = &args.log_level {
+            rslt.overrides.push((String::from("log_level"), log_level.clone().into()));
+        }
+        rslt
+    }
+
+    /// When the settings file was last modified, if it can be told
+    pub fn modified_at(&self) -> Option<SystemTime> {
+        std::fs::metadata(Path::new(&self.path)).and_then(|m| m.modified()).ok()
+    }
+}
+
+/// Reasons the settings could not be used
+#[derive(Debug)]
+pub enum SettingsError {
+    /// The settings file could not be read or parsed
+    Read(ConfigError),
+    /// The settings were read but are inconsistent
+    Invalid(Vec<String>),
+}
+
+impl std::fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingsError::Read(e) => write!(f, "{}", e),
+            SettingsError::Invalid(problems) => write!(f, "{}", problems.join("; ")),
+        }
+    }
 }
 
-lazy_static! {
-    static ref SHARED: ServerSettings =
-        ServerSettings::read_default().expect("Configuration failure");
+impl From<ConfigError> for SettingsError {
+    fn from(e: ConfigError) -> Self {
+        SettingsError::Read(e)
+    }
 }
 
 impl ServerSettings {
-    /// Get the active settings instance
-    pub fn shared() -> &'static Self {
-        &SHARED
+    /// Keep the settings that cannot change while running at their `live` values.
+    /// Returns the names of such settings that were different.
+    pub fn keep_restart_only(&mut self, live: &Self) -> ReloadReport {
+        let mut report = ReloadReport::default();
+        if self.http.bind != live.http.bind {
+            report.restart_required.push(String::from("http.bind"));
+            self.http.bind = live.http.bind.clone();
+        }
+        if self.http.port != live.http.port {
+            report.restart_required.push(String::from("http.port"));
+            self.http.port = live.http.port;
+        }
+        if self.http.ws_port != live.http.ws_port {
+            report.restart_required.push(String::from("http.ws_port"));
+            self.http.ws_port = live.http.ws_port;
+        }
+        report
+    }
+
+    /// Read the settings from the source and check them for consistency
+    pub fn read_validated(source: &SettingsSource) -> Result<Self, SettingsError> {
+        let settings = Self::read(source)?;
+        let problems = settings.validate();
+        if problems.is_empty() {
+            Ok(settings)
+        } else {
+            Err(SettingsError::Invalid(problems))
+        }
     }
 
-    /// Read the settings file at default location
-    pub fn read_default() -> Result<Self, ConfigError> {
-        Self::read("config.toml")
+    /// Read the settings file at a specified location, applying the overrides
+    pub fn read(source: &SettingsSource) -> Result<Self, ConfigError> {
+        debug!("Reading settings file from {}", source.path);
+        let mut s = Config::new();
+        s.merge(File::with_name(&source.path))?;
+        for (key, value) in source.overrides.iter() {
+            trace!("Override setting {} = {}", key, value);
+            s.set(key, value.clone())?;
+        }
+        s.try_into()
     }
 
-    /// Read the settings file at a specified location
-    pub fn read(fpath: &str) -> Result<Self, ConfigError> {
-        debug!("Reading settings file from {}", fpath);
+    /// Parse the settings from a string in the settings file format
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
         let mut s = Config::new();
-        s.merge(File::with_name(fpath))?;
+        s.merge(File::from_str(text, config::FileFormat::Toml))?;
         s.try_into()
     }
+
+    /// Check the settings for consistency, returning a description of every problem found
+    pub fn validate(&self) -> Vec<String> {
+        use std::net::ToSocketAddrs;
+        use std::str::FromStr;
+        let mut problems: Vec<String> = vec![];
+
+        if let Err(e) = (self.http.bind.as_str(), self.http.port).to_socket_addrs() {
+            problems.push(format!("http.bind: \"{}\" is not a usable address: {}", self.http.bind, e));
+        }
+        if self.http.port == self.http.ws_port && self.http.port != 0 {
+            problems.push(format!("http.port and http.ws_port are both set to {}", self.http.port));
+        }
+        if self.http.ws_ping_interval == 0 {
+            problems.push(String::from("http.ws_ping_interval must be at least 1 second"));
+        }
+        if self.http.ws_pong_timeout <= self.http.ws_ping_interval {
+            problems.push(format!(
+                "http.ws_pong_timeout ({}) must be longer than http.ws_ping_interval ({})",
+                self.http.ws_pong_timeout, self.http.ws_ping_interval
+            ));
+        }
+        if self.http.ws_queue_size == 0 {
+            problems.push(String::from("http.ws_queue_size must be at least 1"));
+        }
+        if self.http.bpm_precision > 6 {
+            problems.push(format!("http.bpm_precision ({}) must be at most 6 decimals", self.http.bpm_precision));
+        }
+        if self.http.bpm_min_delta.is_nan() || self.http.bpm_min_delta < 0.0 {
+            problems.push(format!("http.bpm_min_delta ({}) must not be negative", self.http.bpm_min_delta));
+        }
+        let webroot = Path::new(&self.http.webroot);
+        if !webroot.is_dir() {
+            problems.push(format!("http.webroot: \"{}\" is not a folder", webroot.display()));
+        } else if let Err(e) = std::fs::read_dir(webroot) {
+            problems.push(format!("http.webroot: \"{}\" cannot be read: {}", webroot.display(), e));
+        }
+        if let Some(level) = &self.log_level {
+            if log::LevelFilter::from_str(level).is_err() {
+                problems.push(format!("log_level: \"{}\" is not a log level (Error, Warn, Info, Debug, Trace)", level));
+            }
+        }
+
+        let mixing = &self.mixing;
+        if mixing.deck_list.is_empty() {
+            problems.push(String::from("mixing.deck_list is empty"));
+        }
+        for (idx, deck) in mixing.deck_list.iter().enumerate() {
+            if mixing.deck_list[..idx].contains(deck) {
+                problems.push(format!("mixing.deck_list: deck {} is listed more than once", deck));
+            } else if !mixing.deck_channel_map.contains_key(deck) {
+                problems.push(format!("mixing.deck_channel_map: deck {} has no channel assigned", deck));
+            }
+        }
+        let mut mapped_decks: Vec<&Deck> = mixing.deck_channel_map.keys().collect();
+        mapped_decks.sort();
+        for deck in mapped_decks {
+            if !mixing.deck_list.contains(deck) {
+                problems.push(format!("mixing.deck_channel_map: deck {} is not in mixing.deck_list", deck));
+            }
+        }
+
+        if mixing.traktor_timeout == 0 {
+            problems.push(String::from("mixing.traktor_timeout must be at least 1 second"));
+        }
+
+        if mixing.bpm_tolerance.is_nan() || mixing.bpm_tolerance < 0.0 {
+            problems.push(format!("mixing.bpm_tolerance ({}) must not be negative", mixing.bpm_tolerance));
+        }
+
+        let overrides_file = Path::new(&mixing.overrides_file);
+        if overrides_file.is_dir() {
+            problems.push(format!("mixing.overrides_file: \"{}\" is a folder", overrides_file.display()));
+        }
+
+        if Path::new(&mixing.stats_file).is_dir() {
+            problems.push(format!("mixing.stats_file: \"{}\" is a folder", mixing.stats_file));
+        }
+        if mixing.stats_save_interval == 0 {
+            problems.push(String::from("mixing.stats_save_interval must be at least 1 second"));
+        }
+
+        let cover = Path::new(&mixing.default_cover);
+        if !cover.is_file() {
+            problems.push(format!("mixing.default_cover: file \"{}\" does not exist", cover.display()));
+        } else {
+            match infer::get_from_path(cover) {
+                Ok(Some(mime)) if mime.matcher_type() == infer::MatcherType::IMAGE => {}
+                Ok(Some(mime)) => problems.push(format!("mixing.default_cover: \"{}\" is not an image file: {}", cover.display(), mime)),
+                Ok(None) => problems.push(format!("mixing.default_cover: could not find mime type of \"{}\"", cover.display())),
+                Err(e) => problems.push(format!("mixing.default_cover: \"{}\" cannot be read: {}", cover.display(), e)),
+            }
+        }
+
+        let mut output_paths: Vec<&String> = self.outputs.files.keys().collect();
+        output_paths.sort();
+        if !self.outputs.artwork.is_empty() {
+            output_paths.push(&self.outputs.artwork);
+        }
+        for path in output_paths {
+            if Path::new(path).is_dir() {
+                problems.push(format!("outputs: \"{}\" is a folder", path));
+            }
+        }
+
+        if !self.obs.url.is_empty() && !self.obs.url.starts_with("ws://") && !self.obs.url.starts_with("wss://") {
+            problems.push(format!("obs.url ({}) must start with ws:// or wss://", self.obs.url));
+        }
+        if self.obs.reconnect_interval == 0 {
+            problems.push(String::from("obs.reconnect_interval must be at least 1 second"));
+        }
+        for (idx, rule) in self.obs.rules.iter().enumerate() {
+            if let Some(problem) = rule.action.validate() {
+                problems.push(format!("obs.rules[{}]: {}", idx, problem));
+            }
+        }
+
+        for (idx, stream) in self.outputs.streams.iter().enumerate() {
+            if !stream.url.starts_with("http://") && !stream.url.starts_with("https://") {
+                problems.push(format!("outputs.streams[{}]: url ({}) must start with http:// or https://", idx, stream.url));
+            }
+            if stream.server == StreamServer::Icecast && !stream.mount.starts_with('/') {
+                problems.push(format!("outputs.streams[{}]: mount ({}) must start with /", idx, stream.mount));
+            }
+        }
+
+        for (idx, rule) in self.cleanup.iter().enumerate() {
+            if let Some(problem) = rule.validate() {
+                problems.push(format!("cleanup[{}]: {}", idx, problem));
+            }
+        }
+
+        problems
+    }
+
+    /// Effective settings in the settings file format, with the passwords that are set masked
+    pub fn to_toml(&self) -> Result<String, String> {
+        // Going through Value lets the serializer put plain values before tables
+        let mut value = toml::Value::try_from(self).map_err(|e| e.to_string())?;
+        let mask = |password: Option<&mut toml::Value>| {
+            if let Some(password) = password.filter(|p| p.as_str().is_some_and(|p| !p.is_empty())) {
+                *password = toml::Value::String(String::from("***"));
+            }
+        };
+        mask(value.get_mut("obs").and_then(|obs| obs.get_mut("password")));
+        if let Some(streams) = value.get_mut("outputs").and_then(|outputs| outputs.get_mut("streams")).and_then(|s| s.as_array_mut()) {
+            for stream in streams.iter_mut() {
+                mask(stream.get_mut("password"));
+            }
+        }
+        toml::to_string_pretty(&value).map_err(|e| e.to_string())
+    }
+
+    /// Log level filter to apply
+    pub fn log_level_filter(&self) -> log::LevelFilter {
+        use std::str::FromStr;
+        let log_level = self.log_level.as_deref().unwrap_or("Info");
+        log::LevelFilter::from_str(log_level).unwrap_or(log::LevelFilter::Info)
+    }
 }
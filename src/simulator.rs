@@ -0,0 +1,254 @@
+use super::{
+    api::{deck::DeckStatus, Channel, Deck},
+    app::App,
+    error::{RelayError, Result},
+    http_server,
+    status::Ingest,
+};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// Tracks to play in the simulation along with the mixing schedule
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Playlist {
+    /// Seconds a track plays alone before the next one is mixed in
+    pub solo_time: f32,
+    /// Seconds the transition between two tracks takes
+    pub mix_time: f32,
+    /// Seconds between elapsed time updates
+    pub tick_interval: f32,
+    /// Tracks in the same format Traktor sends on deck load
+    pub tracks: Vec<DeckStatus>,
+}
+
+impl Default for Playlist {
+    fn default() -> Self {
+        Self {
+            solo_time: 30.0,
+            mix_time: 10.0,
+            tick_interval: 1.0,
+            tracks: vec![],
+        }
+    }
+}
+
+impl Playlist {
+    /// Read a playlist from a JSON file
+    pub fn read(path: &str) -> Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let playlist: Self = serde_json::from_reader(std::io::BufReader::new(file))
+            .map_err(|e| RelayError::BadRequest(format!("{}: {}", path, e)))?;
+        if playlist.tracks.is_empty() {
+            return Err(RelayError::BadRequest(format!("{}: playlist has no tracks", path)));
+        }
+        if !playlist.tick_interval.is_finite() || playlist.tick_interval <= 0.0 {
+            return Err(RelayError::BadRequest(format!("{}: tickInterval must be more than 0 seconds", path)));
+        }
+        let times = [playlist.solo_time, playlist.mix_time];
+        if times.iter().any(|time| !time.is_finite() || *time < 0.0) {
+            return Err(RelayError::BadRequest(format!("{}: soloTime and mixTime must be 0 seconds or more", path)));
+        }
+        if times.iter().all(|time| *time == 0.0) {
+            return Err(RelayError::BadRequest(format!("{}: soloTime and mixTime cannot both be 0", path)));
+        }
+        Ok(playlist)
+    }
+}
+
+/// A deck that is currently playing in the simulation
+struct Playing {
+    deck: Deck,
+    channel: Channel,
+    bpm: f32,
+    tempo: f32,
+    elapsed: f32,
+}
+
+struct Simulator {
+    playlist: Playlist,
+    decks: Vec<(Deck, Channel)>,
+    app: Arc<App>,
+    /// Calls made since the last tick, sent together on the next one
+    calls: Vec<(Ingest, Option<String>, serde_json::Value)>,
+    playing: Vec<Playing>,
+    master_bpm: f32,
+}
+
+/// Start playing the tracks from the playlist at `path` on the decks of the relay, until it shuts down.
+/// Returns the simulation task.
+pub fn spawn_simulation(app: &Arc<App>, path: &str) -> Result<JoinHandle<()>> {
+    let playlist = Playlist::read(path)?;
+    let mixing = &app.settings().mixing;
+    let decks: Vec<(Deck, Channel)> = mixing
+        .deck_list
+        .iter()
+        .filter_map(|deck| mixing.deck_channel_map.get(deck).map(|chan| (deck.clone(), *chan)))
+        .take(2)
+        .collect();
+    if decks.is_empty() {
+        return Err(RelayError::BadRequest(String::from("no decks with channels configured to simulate on")));
+    }
+
+    info!("Simulating Traktor with {} tracks from {}", playlist.tracks.len(), path);
+    let mut sim = Simulator {
+        playlist,
+        decks,
+        app: app.clone(),
+        calls: vec![],
+        playing: vec![],
+        master_bpm: 0.0,
+    };
+    Ok(tokio::spawn(async move {
+        sim.run().await;
+        debug!("Simulation stopped");
+    }))
+}
+
+impl Simulator {
+    async fn run(&mut self) {
+        let track_count = self.playlist.tracks.len();
+        for (n, idx) in (0..track_count).cycle().enumerate() {
+            let (deck, channel) = self.decks[n % self.decks.len()].clone();
+            let track = self.playlist.tracks[idx].clone();
+            debug!("Simulation loads track {} on deck {}", idx, deck);
+
+            // With a single deck there is nothing to mix with
+            if let Some(pos) = self.playing.iter().position(|p| p.deck == deck) {
+                let outgoing = self.playing.remove(pos);
+                self.stop(&outgoing);
+            }
+
+            let bpm = track.bpm;
+            self.send(Ingest::UpdateChannel, Some(channel.to_string()), json!({ "isOnAir": false }));
+            let mut body = match serde_json::to_value(track) {
+                Ok(body) => body,
+                Err(e) => {
+                    error!("Could not load track {} of the simulation: {}", idx, e);
+                    continue;
+                }
+            };
+            body["isPlaying"] = json!(false);
+            body["elapsedTime"] = json!(0.0);
+            self.send(Ingest::DeckLoaded, Some(deck.clone()), body);
+
+            // Incoming track is synced to the master clock
+            let tempo = if self.playing.is_empty() || bpm <= 0.0 { 1.0 } else { self.master_bpm / bpm };
+            let incoming = Playing { deck, channel, bpm, tempo, elapsed: 0.0 };
+            self.send(Ingest::UpdateDeck, Some(incoming.deck.clone()), json!({ "isPlaying": true, "tempo": tempo }));
+            self.send(Ingest::UpdateChannel, Some(channel.to_string()), json!({ "isOnAir": true }));
+
+            if self.playing.is_empty() {
+                self.master_bpm = bpm;
+                self.send_clock(&incoming.deck);
+                self.playing.push(incoming);
+            } else {
+                self.playing.push(incoming);
+                if !self.mix().await {
+                    return;
+                }
+                let outgoing = self.playing.remove(0);
+                self.stop(&outgoing);
+                if let Some(master) = self.playing.first() {
+                    let deck = master.deck.clone();
+                    self.master_bpm = master.bpm * master.tempo;
+                    self.send_clock(&deck);
+                }
+            }
+
+            if !self.run_for(self.playlist.solo_time, |_, _| {}).await {
+                return;
+            }
+        }
+    }
+
+    /// Ramp the tempo from the outgoing track to the incoming one.
+    /// Returns false if the relay is shutting down.
+    async fn mix(&mut self) -> bool {
+        let from_bpm = self.master_bpm;
+        let to_bpm = self.playing.last().map(|p| p.bpm).unwrap_or(from_bpm);
+        let mix_time = self.playlist.mix_time;
+        self.run_for(mix_time, |sim, progress| {
+            if to_bpm <= 0.0 || (to_bpm - from_bpm).abs() < f32::EPSILON {
+                return;
+            }
+            sim.master_bpm = from_bpm + (to_bpm - from_bpm) * progress;
+            let master_bpm = sim.master_bpm;
+            for playing in sim.playing.iter_mut() {
+                if playing.bpm > 0.0 {
+                    playing.tempo = master_bpm / playing.bpm;
+                }
+            }
+            for idx in 0..sim.playing.len() {
+                let (deck, tempo) = (sim.playing[idx].deck.clone(), sim.playing[idx].tempo);
+                sim.send(Ingest::UpdateDeck, Some(deck), json!({ "tempo": tempo }));
+            }
+            // Incoming deck takes over the master clock halfway through
+            let master = if progress < 0.5 { sim.playing.first() } else { sim.playing.last() };
+            if let Some(master) = master.map(|p| p.deck.clone()) {
+                sim.send_clock(&master);
+            }
+        })
+        .await
+    }
+
+    /// Let the playing decks advance for `secs`, calling `on_tick` with the progress from 0 to 1 on every tick.
+    /// Returns false if the relay is shutting down.
+    async fn run_for<T>(&mut self, secs: f32, on_tick: T) -> bool
+    where
+        T: Fn(&mut Self, f32),
+    {
+        let interval = self.playlist.tick_interval;
+        let mut passed = 0.0;
+        while passed < secs {
+            let step = interval.min(secs - passed);
+            self.flush().await;
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs_f32(step)) => {}
+                _ = self.app.shutdown_requested() => return false,
+            }
+            passed += step;
+            for idx in 0..self.playing.len() {
+                let playing = &mut self.playing[idx];
+                playing.elapsed += step * playing.tempo;
+                let (deck, elapsed) = (playing.deck.clone(), playing.elapsed);
+                self.send(Ingest::UpdateDeck, Some(deck), json!({ "elapsedTime": elapsed }));
+            }
+            on_tick(self, passed / secs);
+        }
+        self.flush().await;
+        true
+    }
+
+    fn stop(&mut self, playing: &Playing) {
+        self.send(Ingest::UpdateChannel, Some(playing.channel.to_string()), json!({ "isOnAir": false }));
+        self.send(Ingest::UpdateDeck, Some(playing.deck.clone()), json!({ "isPlaying": false }));
+    }
+
+    fn send_clock(&mut self, deck: &str) {
+        let bpm = self.master_bpm;
+        self.send(Ingest::UpdateMasterClock, None, json!({ "deck": deck, "bpm": bpm }));
+    }
+
+    fn send(&mut self, kind: Ingest, id: Option<String>, body: serde_json::Value) {
+        self.calls.push((kind, id, body));
+    }
+
+    /// Make the calls sent since the last tick, on the blocking pool like the HTTP calls from Traktor
+    async fn flush(&mut self) {
+        let calls = std::mem::take(&mut self.calls);
+        let app = self.app.clone();
+        let made = tokio::task::spawn_blocking(move || {
+            for (kind, id, body) in calls {
+                if let Err(e) = http_server::ingest(&app, kind, id, body) {
+                    error!("Simulated {} call failed: {}", kind.route(), e);
+                }
+            }
+        });
+        if let Err(e) = made.await {
+            error!("Simulated calls panicked: {}", e);
+        }
+    }
+}
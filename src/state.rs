@@ -0,0 +1,290 @@
+use super::{
+    api::{channel::*, deck::*, master_clock::*, stats::TrackStats, Channel, Deck},
+    mix_hints::{self, MixHint},
+    overrides::{self, DeckOverrides, MetadataOverride, OverrideScope},
+    settings::MixingSettings,
+    status::Ingest,
+};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How many played tracks to remember
+const HISTORY_LENGTH: usize = 200;
+
+/// A track that was played on air
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    /// Deck the track was played on
+    pub deck: Deck,
+    /// Track as it was when it started playing on air
+    pub track: DeckStatus,
+    /// When the track started playing on air, in seconds since the Unix epoch
+    pub started_at: f64,
+}
+
+/// Everything the relay knows about the mix.
+/// An `App` keeps it behind a single lock, so every handler sees and changes it as a whole.
+#[derive(Debug, Default)]
+pub struct RelayState {
+    /// Status of the decks by deck letter
+    pub decks: HashMap<Deck, DeckStatus>,
+    /// Status of the mixer channels by channel number
+    pub channels: HashMap<Channel, ChannelStatus>,
+    /// Master clock status
+    pub clock: MasterClock,
+    /// When Traktor has last made each of the calls
+    last_ingest: [Option<Instant>; 4],
+    /// Tracks played on air, oldest first
+    history: VecDeque<HistoryEntry>,
+    /// Decks whose current track is already in the history
+    in_history: Vec<Deck>,
+    /// Metadata overrides of the tracks currently loaded, by deck letter
+    deck_overrides: HashMap<Deck, MetadataOverride>,
+    /// Metadata overrides of the track files, by file path
+    pub file_overrides: HashMap<String, MetadataOverride>,
+    /// Plays of the track files, by file path
+    pub track_stats: HashMap<String, TrackStats>,
+    /// Since when the airtime of the decks playing on air was last counted
+    airtime_since: HashMap<Deck, Instant>,
+    /// Whether the track statistics have changed since they were last saved
+    stats_changed: bool,
+}
+
+impl RelayState {
+    /// Create an empty state with the mapped channels on air
+    pub fn new(mixing: &MixingSettings) -> Self {
+        let mut rslt = Self::default();
+        rslt.preheat_channels(mixing);
+        rslt
+    }
+
+    /// Mark the channels from the deck channel map as on air, unless their status is already known
+    pub fn preheat_channels(&mut self, mixing: &MixingSettings) {
+        for (_, channel) in mixing.deck_channel_map.iter() {
+            if !self.channels.contains_key(channel) {
+                trace!("Preheat channel matrix data {}", channel);
+                self.channels.insert(*channel, ChannelStatus { is_on_air: true });
+            }
+        }
+    }
+
+    /// Put a freshly loaded track onto the deck
+    pub fn load_deck(&mut self, id: Deck, mut status: DeckStatus) {
+        status.deck = Some(id.clone());
+        status.update_key_notation();
+        if let Some(since) = self.airtime_since.remove(&id) {
+            self.add_airtime(&id, since, Instant::now());
+        }
+        self.in_history.retain(|deck| deck != &id);
+        self.deck_overrides.remove(&id);
+        self.decks.insert(id, status);
+    }
+
+    /// Apply a delta to the track on the deck.
+    /// Returns whether the change affects the Now Playing status, or `None` if nothing was loaded on the deck.
+    pub fn update_deck(&mut self, id: &str, delta: DeckStatusUpdate, more_events: bool) -> Option<bool> {
+        self.decks.get_mut(id).map(|deck| deck.update(delta, more_events))
+    }
+
+    /// Tracks on the decks that the listeners can hear, with the metadata overrides applied
+    pub fn songs_on_air(&self, mixing: &MixingSettings) -> Vec<DeckStatus> {
+        let mut songs = super::logic::get_songs_on_air(&self.decks, &self.channels, mixing);
+        for song in songs.iter_mut() {
+            song.stats = self.track_stats.get(&song.file_path).cloned();
+            self.apply_overrides(song, mixing);
+            song.update_tempo(&self.clock, mixing.bpm_tolerance);
+        }
+        songs
+    }
+
+    /// The track on air that most recently started playing, or else the first track on air
+    pub fn current_track(&self, mixing: &MixingSettings) -> Option<DeckStatus> {
+        let mut songs = self.songs_on_air(mixing);
+        let latest = self
+            .history
+            .iter()
+            .rev()
+            .find_map(|entry| songs.iter().position(|song| song.is_playing && song.deck.as_ref() == Some(&entry.deck)));
+        match latest {
+            Some(idx) => Some(songs.swap_remove(idx)),
+            None if songs.is_empty() => None,
+            None => Some(songs.swap_remove(0)),
+        }
+    }
+
+    /// Tempo of the loaded decks against the master clock, in the deck list order
+    pub fn deck_tempos(&self, mixing: &MixingSettings) -> Vec<DeckTempo> {
+        mixing
+            .deck_list
+            .iter()
+            .filter_map(|deck| self.decks.get(deck).map(|track| track.tempo(deck, &self.clock, mixing.bpm_tolerance)))
+            .collect()
+    }
+
+    /// How well the loaded decks mix with each other
+    pub fn mix_hints(&self, mixing: &MixingSettings) -> Vec<MixHint> {
+        mix_hints::compute(&self.decks, mixing)
+    }
+
+    /// Whether the track loaded into the deck is shown as an ID
+    pub fn is_hidden(&self, id: &str, mixing: &MixingSettings) -> bool {
+        match self.decks.get(id) {
+            Some(deck) => {
+                let mut track = deck.clone();
+                self.apply_overrides(&mut track, mixing);
+                track.is_hidden
+            }
+            None => false,
+        }
+    }
+
+    /// Replace the metadata of the track with its overrides, the deck override taking precedence,
+    /// then with the ID placeholders if the track is hidden
+    fn apply_overrides(&self, track: &mut DeckStatus, mixing: &MixingSettings) {
+        track.is_hidden = false;
+        let file_override = self.file_overrides.get(&track.file_path);
+        let deck_override = track.deck.as_ref().and_then(|deck| self.deck_overrides.get(deck));
+        if let Some(file_override) = file_override {
+            file_override.apply(track);
+        }
+        if let Some(deck_override) = deck_override {
+            deck_override.apply(track);
+        }
+
+        let hidden = deck_override
+            .and_then(|o| o.hidden)
+            .or_else(|| file_override.and_then(|o| o.hidden))
+            .unwrap_or_else(|| overrides::has_id_marker(track, mixing));
+        if hidden {
+            overrides::hide(track, mixing);
+        }
+    }
+
+    /// Overrides in effect for the track loaded into the deck
+    pub fn overrides(&self, id: &str) -> Option<DeckOverrides> {
+        let deck = self.decks.get(id)?;
+        Some(DeckOverrides {
+            deck: self.deck_overrides.get(id).cloned(),
+            file: self.file_overrides.get(&deck.file_path).cloned(),
+        })
+    }
+
+    /// Set the override of the track loaded into the deck, removing it if it is empty.
+    /// Returns `None` if nothing was loaded on the deck.
+    pub fn set_override(&mut self, id: &str, scope: OverrideScope, metadata: MetadataOverride) -> Option<DeckOverrides> {
+        let file_path = self.decks.get(id)?.file_path.clone();
+        match (scope, metadata.is_empty()) {
+            (OverrideScope::Deck, true) => {
+                self.deck_overrides.remove(id);
+            }
+            (OverrideScope::Deck, false) => {
+                self.deck_overrides.insert(id.to_string(), metadata);
+            }
+            (OverrideScope::File, true) => {
+                self.file_overrides.remove(&file_path);
+            }
+            (OverrideScope::File, false) => {
+                self.file_overrides.insert(file_path, metadata);
+            }
+        }
+        self.overrides(id)
+    }
+
+    /// Add the tracks that have just started playing on air to the history, and count the plays
+    /// and the airtime of the tracks that are not hidden. Returns whether a play was counted.
+    pub fn update_history(&mut self, mixing: &MixingSettings) -> bool {
+        let now = Instant::now();
+        let songs = self.songs_on_air(mixing);
+        for (deck, since) in std::mem::take(&mut self.airtime_since) {
+            self.add_airtime(&deck, since, now);
+        }
+        for track in songs.iter().filter(|track| track.is_playing && !track.is_hidden) {
+            if let Some(deck) = &track.deck {
+                self.airtime_since.insert(deck.clone(), now);
+            }
+        }
+
+        let mut counted = false;
+        for track in songs {
+            let deck = match &track.deck {
+                Some(deck) => deck.clone(),
+                None => continue,
+            };
+            if !track.is_playing || self.in_history.contains(&deck) {
+                continue;
+            }
+            debug!("Track {} - {} started playing on air on deck {}", track.artist, track.title, deck);
+            let started_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+            if !track.is_hidden {
+                if let Some(loaded) = self.decks.get(&deck) {
+                    let stats = self.track_stats.entry(loaded.file_path.clone()).or_default();
+                    stats.play_count += 1;
+                    stats.last_played = Some(started_at);
+                    stats.artist.clone_from(&loaded.artist);
+                    stats.title.clone_from(&loaded.title);
+                    counted = true;
+                    self.stats_changed = true;
+                }
+            }
+            self.in_history.push(deck.clone());
+            self.history.push_back(HistoryEntry { deck, track, started_at });
+            if self.history.len() > HISTORY_LENGTH {
+                self.history.pop_front();
+            }
+        }
+        counted
+    }
+
+    /// Count the airtime of the decks playing on air up to now, while the tracks on air stay the same
+    pub fn update_airtime(&mut self) {
+        let now = Instant::now();
+        for (deck, since) in std::mem::take(&mut self.airtime_since) {
+            self.add_airtime(&deck, since, now);
+            self.airtime_since.insert(deck, now);
+        }
+    }
+
+    /// Count the time since `since` as airtime of the track loaded into the deck
+    fn add_airtime(&mut self, id: &str, since: Instant, now: Instant) {
+        let path = match self.decks.get(id) {
+            Some(deck) => &deck.file_path,
+            None => return,
+        };
+        if let Some(stats) = self.track_stats.get_mut(path) {
+            stats.airtime += now.saturating_duration_since(since).as_secs_f64();
+            self.stats_changed = true;
+        }
+    }
+
+    /// Whether the track statistics have changed since this was last asked
+    pub fn take_stats_changed(&mut self) -> bool {
+        std::mem::take(&mut self.stats_changed)
+    }
+
+    /// Up to `limit` most recently played tracks, oldest first
+    pub fn history(&self, limit: Option<usize>) -> Vec<HistoryEntry> {
+        let skip = limit.map(|limit| self.history.len().saturating_sub(limit)).unwrap_or(0);
+        self.history.iter().skip(skip).cloned().collect()
+    }
+
+    /// Remember that Traktor has just made a call
+    pub fn record_ingest(&mut self, ingest: Ingest) {
+        self.last_ingest[ingest as usize] = Some(Instant::now());
+    }
+
+    /// Time since Traktor has made the specified call, if ever
+    pub fn since(&self, ingest: Ingest) -> Option<Duration> {
+        self.last_ingest[ingest as usize].map(|at| at.elapsed())
+    }
+
+    /// Time since Traktor has made any call, if ever
+    pub fn since_any(&self) -> Option<Duration> {
+        self.last_ingest.iter().flatten().map(|at| at.elapsed()).min()
+    }
+
+    /// Whether Traktor has made any call within `timeout`
+    pub fn traktor_connected(&self, timeout: Duration) -> bool {
+        self.since_any().map(|since| since < timeout).unwrap_or(false)
+    }
+}
@@ -0,0 +1,93 @@
+use super::{api::stats::TrackStats, app::App, error::Result};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// Statistics of a track with its file path
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsEntry<'a> {
+    pub path: &'a str,
+    #[serde(flatten)]
+    pub stats: &'a TrackStats,
+}
+
+/// What the top tracks are ranked by
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum TopOrder {
+    /// Play count, the most recently played first among equals
+    #[default]
+    Plays,
+    /// Total airtime
+    Airtime,
+}
+
+/// Up to `limit` tracks ranked by `order`
+pub fn top(stats: &HashMap<String, TrackStats>, order: TopOrder, limit: usize) -> Vec<StatsEntry<'_>> {
+    let mut entries: Vec<StatsEntry> = stats.iter().map(|(path, stats)| StatsEntry { path, stats }).collect();
+    entries.sort_by(|a, b| {
+        let rank = match order {
+            TopOrder::Plays => b.stats.play_count.cmp(&a.stats.play_count),
+            TopOrder::Airtime => b.stats.airtime.partial_cmp(&a.stats.airtime).unwrap_or(Ordering::Equal),
+        };
+        rank.then_with(|| b.stats.last_played.partial_cmp(&a.stats.last_played).unwrap_or(Ordering::Equal))
+            .then_with(|| a.path.cmp(b.path))
+    });
+    entries.truncate(limit);
+    entries
+}
+
+/// Statistics of the track with the artist and title, ignoring case
+pub fn find_by_name<'a>(stats: &'a HashMap<String, TrackStats>, artist: &str, title: &str) -> Option<StatsEntry<'a>> {
+    stats
+        .iter()
+        .filter(|(_, stats)| stats.artist.to_lowercase() == artist.to_lowercase() && stats.title.to_lowercase() == title.to_lowercase())
+        .max_by(|(_, a), (_, b)| a.last_played.partial_cmp(&b.last_played).unwrap_or(Ordering::Equal))
+        .map(|(path, stats)| StatsEntry { path, stats })
+}
+
+/// Read the track statistics, keyed by track file path
+pub fn load(path: &str) -> Result<HashMap<String, TrackStats>> {
+    if path.is_empty() || !Path::new(path).exists() {
+        debug!("No statistics file at {:?}", path);
+        return Ok(HashMap::new());
+    }
+    let stats: HashMap<String, TrackStats> = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    info!("Loaded statistics of {} track(s) from {}", stats.len(), path);
+    Ok(stats)
+}
+
+/// Write the track statistics; nothing is written if `path` is empty
+pub fn save(path: &str, stats: &HashMap<String, TrackStats>) -> Result<()> {
+    if path.is_empty() {
+        return Ok(());
+    }
+    super::logic::write_atomic(path, &serde_json::to_vec(stats)?)?;
+    debug!("Saved statistics of {} track(s) to {}", stats.len(), path);
+    Ok(())
+}
+
+/// Save the statistics in the background whenever they have changed, until the relay shuts down
+pub fn spawn_saver(app: Arc<App>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        debug!("Starting statistics saver");
+        loop {
+            let interval = Duration::from_secs(app.settings().mixing.stats_save_interval);
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = app.shutdown_requested() => break,
+            }
+            // Like the HTTP handlers, the state lock is taken on the blocking pool
+            let save_app = app.clone();
+            if let Err(e) = tokio::task::spawn_blocking(move || save_app.save_changed_stats()).await {
+                error!("Saving the statistics panicked: {}", e);
+            }
+        }
+    })
+}
@@ -0,0 +1,94 @@
+use super::{
+    app::App,
+    error::Result,
+    ws_server::{self, Topic},
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// Calls Traktor makes to the relay
+#[derive(Debug, Clone, Copy)]
+pub enum Ingest {
+    DeckLoaded = 0,
+    UpdateDeck,
+    UpdateMasterClock,
+    UpdateChannel,
+}
+
+impl Ingest {
+    /// Name of the route used for the call
+    pub fn route(&self) -> &'static str {
+        match self {
+            Ingest::DeckLoaded => "deckLoaded",
+            Ingest::UpdateDeck => "updateDeck",
+            Ingest::UpdateMasterClock => "updateMasterClock",
+            Ingest::UpdateChannel => "updateChannel",
+        }
+    }
+
+    /// Find the call by its route name
+    pub fn from_route(route: &str) -> Option<Self> {
+        [Ingest::DeckLoaded, Ingest::UpdateDeck, Ingest::UpdateMasterClock, Ingest::UpdateChannel]
+            .iter()
+            .find(|kind| kind.route() == route)
+            .copied()
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TraktorConnectionEvent {
+    pub event: &'static str,
+    pub traktor_connected: bool,
+    pub seconds_since_last_update: Option<f64>,
+}
+
+/// Whether Traktor has been talking to the relay recently, and how long ago it last did
+fn traktor_activity(app: &App) -> Result<(bool, Option<Duration>)> {
+    let timeout = Duration::from_secs(app.settings().mixing.traktor_timeout);
+    let state = app.state()?;
+    Ok((state.traktor_connected(timeout), state.since_any()))
+}
+
+/// Watch for Traktor going silent or coming back and let the clients know
+pub fn spawn_watchdog(app: Arc<App>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        debug!("Starting Traktor watchdog");
+        let mut was_connected = false;
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = app.shutdown_requested() => break,
+            }
+            // Like the HTTP handlers, the state lock is taken on the blocking pool
+            let activity_app = app.clone();
+            let (connected, since) = match tokio::task::spawn_blocking(move || traktor_activity(&activity_app)).await {
+                Ok(Ok(activity)) => activity,
+                Ok(Err(e)) => {
+                    error!("Could not check Traktor activity: {}", e);
+                    continue;
+                }
+                Err(e) => {
+                    error!("Traktor activity check panicked: {}", e);
+                    continue;
+                }
+            };
+            if connected != was_connected {
+                let seconds_since_last_update = since.map(|d| d.as_secs_f64());
+                if connected {
+                    info!("Traktor is talking to the relay");
+                } else {
+                    warn!("Traktor went silent {:.0}s ago", seconds_since_last_update.unwrap_or_default());
+                }
+                ws_server::ws_push(&app, Topic::TraktorConnectionChanged, &TraktorConnectionEvent {
+                    event: "traktorConnectionChanged",
+                    traktor_connected: connected,
+                    seconds_since_last_update,
+                });
+                was_connected = connected;
+            }
+        }
+    })
+}
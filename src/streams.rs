@@ -0,0 +1,221 @@
+use super::{
+    app::App,
+    error::{RelayError, Result},
+    settings::{StreamServer, StreamSettings},
+    template,
+};
+use hyper::client::{Client, HttpConnector};
+use hyper_rustls::HttpsConnector;
+use hyper::header::{self, HeaderValue};
+use hyper::{Body, Request};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// How long to wait for a streaming server to answer
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Client for the streaming servers, over HTTP or HTTPS
+type StreamClient = Client<HttpsConnector<HttpConnector>>;
+
+/// Song titles of the streams, kept up to date with the current track
+#[derive(Default)]
+pub struct StreamMetadata {
+    /// Wakes up the metadata task when the mix has changed
+    wake: Notify,
+}
+
+impl StreamMetadata {
+    /// Ask for the song titles to be brought up to date
+    pub fn refresh(&self) {
+        self.wake.notify_one();
+    }
+}
+
+/// An update that has failed
+struct Pending {
+    song: String,
+    failures: u32,
+    retry_at: Instant,
+}
+
+/// Where a stream is with its song title
+#[derive(Default)]
+struct StreamStatus {
+    /// Song title the server has last taken
+    sent: Option<String>,
+    /// Update that failed and is going to be retried
+    pending: Option<Pending>,
+}
+
+impl StreamStatus {
+    /// Whether the song should be sent now
+    fn wants(&self, song: &str, stream: &StreamSettings, now: Instant) -> bool {
+        if self.sent.as_deref() == Some(song) {
+            return false;
+        }
+        match &self.pending {
+            Some(pending) if pending.song == song => pending.failures <= stream.retries && pending.retry_at <= now,
+            _ => true,
+        }
+    }
+
+    /// When to try again, unless there is nothing to retry
+    fn retry_at(&self, stream: &StreamSettings) -> Option<Instant> {
+        self.pending.as_ref().filter(|pending| pending.failures <= stream.retries).map(|pending| pending.retry_at)
+    }
+}
+
+/// Stream identity that survives settings reloads
+fn stream_key(stream: &StreamSettings) -> String {
+    format!("{:?} {}{} {}", stream.server, stream.url, stream.mount, stream.sid)
+}
+
+/// Escape the text for a URL query string
+fn percent_encode(text: &str) -> String {
+    let mut rslt = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => rslt.push(byte as char),
+            _ => rslt.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    rslt
+}
+
+/// Request setting the song title of the stream
+fn metadata_request(stream: &StreamSettings, song: &str) -> Result<Request<Body>> {
+    let base = stream.url.trim_end_matches('/');
+    let song = percent_encode(song);
+    let password = percent_encode(&stream.password);
+    let (uri, credentials) = match stream.server {
+        StreamServer::Icecast => (
+            format!("{}/admin/metadata?mount={}&mode=updinfo&song={}", base, percent_encode(&stream.mount), song),
+            Some(format!("{}:{}", stream.user, stream.password)),
+        ),
+        StreamServer::Shoutcast1 => (format!("{}/admin.cgi?pass={}&mode=updinfo&song={}", base, password, song), None),
+        StreamServer::Shoutcast2 => (
+            format!("{}/admin.cgi?sid={}&pass={}&mode=updinfo&song={}", base, stream.sid, password, song),
+            None,
+        ),
+    };
+    let mut request = Request::get(uri.as_str());
+    // SHOUTcast v1 only answers browsers
+    request = request.header(header::USER_AGENT, "Mozilla/5.0 (traktor-obs-relay)");
+    if let Some(credentials) = credentials {
+        let value = HeaderValue::from_str(&format!("Basic {}", base64::encode(credentials)))
+            .map_err(|e| RelayError::Stream(format!("bad credentials: {}", e)))?;
+        request = request.header(header::AUTHORIZATION, value);
+    }
+    request.body(Body::empty()).map_err(|e| RelayError::Stream(format!("bad URL {}: {}", uri, e)))
+}
+
+/// Send the song title to the streaming server
+async fn send(client: &StreamClient, stream: &StreamSettings, song: &str) -> Result<()> {
+    let request = metadata_request(stream, song)?;
+    let response = tokio::time::timeout(REQUEST_TIMEOUT, client.request(request))
+        .await
+        .map_err(|_| RelayError::Stream(String::from("no answer in time")))??;
+    if !response.status().is_success() {
+        return Err(RelayError::Stream(format!("answered {}", response.status())));
+    }
+    Ok(())
+}
+
+/// Song title of the current track for each stream, `None` if there is no track
+fn songs(app: &App) -> Result<Option<Vec<String>>> {
+    let settings = app.settings();
+    let state = app.state()?;
+    let track = match state.current_track(&settings.mixing) {
+        Some(track) => track,
+        None => return Ok(None),
+    };
+    let values = template::values(Some(&track), &state.clock, settings.http.bpm_precision);
+    Ok(Some(settings.outputs.streams.iter().map(|stream| template::render(&stream.song, &values)).collect()))
+}
+
+/// Bring the song titles of the streams up to date whenever the mix changes, until the relay shuts down
+pub fn spawn(app: Arc<App>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let client: StreamClient = Client::builder().build(HttpsConnector::with_webpki_roots());
+        let mut statuses: HashMap<String, StreamStatus> = HashMap::new();
+        let mut retry_at: Option<Instant> = None;
+        loop {
+            let retry = async {
+                match retry_at {
+                    Some(at) => tokio::time::sleep_until(at).await,
+                    None => futures_util::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = app.streams.wake.notified() => {},
+                _ = retry => {},
+                _ = app.shutdown_requested() => break,
+            }
+
+            retry_at = None;
+            let settings = app.settings();
+            if settings.outputs.streams.is_empty() {
+                continue;
+            }
+            // Like the HTTP handlers, the state lock is taken on the blocking pool
+            let songs_app = app.clone();
+            let songs = match tokio::task::spawn_blocking(move || songs(&songs_app)).await {
+                Ok(Ok(Some(songs))) => songs,
+                Ok(Ok(None)) => continue,
+                Ok(Err(e)) => {
+                    error!("Could not render the stream song titles: {}", e);
+                    continue;
+                }
+                Err(e) => {
+                    error!("Stream song titles rendering panicked: {}", e);
+                    continue;
+                }
+            };
+
+            // Streams are sent to all at once, so that a server that does not answer holds up no other
+            let now = Instant::now();
+            let wanted: Vec<(&StreamSettings, String)> = settings
+                .outputs
+                .streams
+                .iter()
+                .zip(songs)
+                .filter(|(stream, song)| statuses.entry(stream_key(stream)).or_default().wants(song, stream, now))
+                .collect();
+            let sent = futures_util::future::join_all(wanted.iter().map(|(stream, song)| send(&client, stream, song))).await;
+            for ((stream, song), sent) in wanted.into_iter().zip(sent) {
+                let status = statuses.entry(stream_key(stream)).or_default();
+                match sent {
+                    Ok(()) => {
+                        debug!("Song title of {} set to {}", stream.url, song);
+                        status.sent = Some(song);
+                        status.pending = None;
+                    }
+                    Err(e) => {
+                        let failures = match &status.pending {
+                            Some(pending) if pending.song == song => pending.failures + 1,
+                            _ => 1,
+                        };
+                        if failures > stream.retries {
+                            error!("Could not set the song title of {}, giving up: {}", stream.url, e);
+                        } else {
+                            warn!("Could not set the song title of {}, trying again: {}", stream.url, e);
+                        }
+                        let retry_at = Instant::now() + Duration::from_millis(stream.retry_delay);
+                        status.pending = Some(Pending { song, failures, retry_at });
+                    }
+                }
+            }
+            retry_at = settings
+                .outputs
+                .streams
+                .iter()
+                .filter_map(|stream| statuses.get(&stream_key(stream)).and_then(|status| status.retry_at(stream)))
+                .min();
+        }
+        debug!("Stream metadata stopped");
+    })
+}
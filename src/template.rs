@@ -0,0 +1,87 @@
+use super::api::{deck::DeckStatus, master_clock::MasterClock};
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// Values of the template placeholders, by placeholder name
+pub type TemplateValues = HashMap<&'static str, String>;
+
+/// Pattern of the `%name%` and `{name}` placeholders
+fn placeholder() -> &'static Regex {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    PLACEHOLDER.get_or_init(|| Regex::new(r"%(\w+)%|\{(\w+)\}").expect("placeholder pattern is valid"))
+}
+
+fn placeholder_name<'t>(caps: &Captures<'t>) -> &'t str {
+    caps.get(1).or_else(|| caps.get(2)).map(|m| m.as_str()).unwrap_or_default()
+}
+
+/// Replace `%name%` and `{name}` placeholders with their values, leaving unknown ones as they are
+pub fn render(template: &str, values: &TemplateValues) -> String {
+    placeholder()
+        .replace_all(template, |caps: &Captures| {
+            let name = placeholder_name(caps);
+            match values.get(name) {
+                Some(value) => value.clone(),
+                None => caps[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+/// Whether the template has a placeholder for any of `names`
+pub fn uses(template: &str, names: &[&str]) -> bool {
+    placeholder().captures_iter(template).any(|caps| names.contains(&placeholder_name(&caps)))
+}
+
+/// Seconds as `m:ss`
+fn format_duration(seconds: f32) -> String {
+    let seconds = seconds.max(0.0).round() as u64;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Placeholder values for the track, empty if there is none, and for the master clock
+/// with the BPM rounded to `precision` decimals
+pub fn values(track: Option<&DeckStatus>, clock: &MasterClock, precision: u32) -> TemplateValues {
+    let precision = precision as usize;
+    let mut values = TemplateValues::new();
+    values.insert("bpm", format!("{:.*}", precision, clock.bpm));
+    values.insert("masterDeck", clock.deck.clone().unwrap_or_default());
+
+    let empty = DeckStatus::default();
+    let known = track.is_some();
+    let track = track.unwrap_or(&empty);
+    let text_fields = [
+        ("artist", &track.artist),
+        ("title", &track.title),
+        ("album", &track.album),
+        ("genre", &track.genre),
+        ("comment", &track.comment),
+        ("comment2", &track.comment2),
+        ("label", &track.label),
+        ("mix", &track.mix),
+        ("remixer", &track.remixer),
+        ("featuring", &track.featuring),
+    ];
+    for (name, value) in IntoIterator::into_iter(text_fields) {
+        values.insert(name, value.clone());
+    }
+    values.insert("deck", track.deck.clone().unwrap_or_default());
+
+    let key = track.resulting_key_notation.as_ref();
+    values.insert("key", key.map(|k| k.musical.clone()).unwrap_or_default());
+    values.insert("camelot", key.map(|k| k.camelot.clone()).unwrap_or_default());
+    values.insert("openKey", key.map(|k| k.open_key.clone()).unwrap_or_default());
+
+    let when_known = |text: String| if known { text } else { String::new() };
+    values.insert("deckBpm", when_known(format!("{:.*}", precision, track.effective_bpm())));
+    values.insert("elapsed", when_known(format_duration(track.elapsed_time)));
+    values.insert("remaining", when_known(format_duration(track.track_length - track.elapsed_time)));
+    values.insert("length", when_known(format_duration(track.track_length)));
+    values.insert(
+        "nextCue",
+        track.next_cue_pos.filter(|_| known).map(|cue| format_duration(cue - track.elapsed_time)).unwrap_or_default(),
+    );
+    values.insert("playCount", track.stats.as_ref().map(|s| s.play_count.to_string()).unwrap_or_default());
+    values
+}
@@ -0,0 +1,142 @@
+use super::{api::Deck, app::App, http_server, settings::HttpSettings};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+/// What to do with a master clock change
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    /// Push the BPM, rounded to the precision, right away
+    Push(f32),
+    /// Push the change once the rate limit allows it
+    Wait,
+    /// Too small to be worth a push
+    Skip,
+}
+
+/// Keeps the master clock pushes to the websocket clients down to changes that matter
+#[derive(Default)]
+pub struct BpmThrottle {
+    state: Mutex<ThrottleState>,
+    /// Wakes up the throttle task when its deadline may have changed
+    wake: Notify,
+}
+
+#[derive(Default)]
+struct ThrottleState {
+    /// Last pushed BPM and master deck
+    sent: Option<(f32, Option<Deck>)>,
+    /// When the last push was made
+    sent_at: Option<Instant>,
+    /// Whether a change is waiting for the rate limit
+    pending: bool,
+    /// When the BPM last moved, until it is reported as settled
+    moved_at: Option<Instant>,
+}
+
+/// Round the BPM to the number of decimals
+pub fn round(bpm: f32, precision: u32) -> f32 {
+    let scale = 10f32.powi(precision as i32);
+    (bpm * scale).round() / scale
+}
+
+impl BpmThrottle {
+    fn lock(&self) -> std::sync::MutexGuard<'_, ThrottleState> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    /// Decide what to do with the new master clock; a `Push` is recorded as made
+    pub fn offer(&self, bpm: f32, deck: Option<&Deck>, cfg: &HttpSettings, now: Instant) -> Verdict {
+        let mut state = self.lock();
+        let rounded = round(bpm, cfg.bpm_precision);
+        if let Some((sent_bpm, sent_deck)) = &state.sent {
+            if sent_deck.as_ref() == deck && (rounded == *sent_bpm || (rounded - sent_bpm).abs() < cfg.bpm_min_delta) {
+                if state.pending {
+                    // Back to what the clients already have: nothing is waiting anymore,
+                    // and the BPM settles from here
+                    state.pending = false;
+                    state.moved_at = Some(now);
+                    self.wake.notify_one();
+                }
+                return Verdict::Skip;
+            }
+        }
+
+        state.moved_at = Some(now);
+        self.wake.notify_one();
+        let rate_limit = Duration::from_millis(cfg.bpm_rate_limit);
+        if state.sent_at.map(|at| now < at + rate_limit).unwrap_or(false) {
+            state.pending = true;
+            return Verdict::Wait;
+        }
+        state.pending = false;
+        state.sent = Some((rounded, deck.cloned()));
+        state.sent_at = Some(now);
+        Verdict::Push(rounded)
+    }
+
+    /// When the throttle has something to do next
+    fn deadline(&self, cfg: &HttpSettings) -> Option<Instant> {
+        self.lock().deadline(cfg)
+    }
+
+    /// Take what is due at `now`: whether the waiting change can be offered again,
+    /// and whether the BPM has stopped moving
+    fn take_due(&self, cfg: &HttpSettings, now: Instant) -> (bool, bool) {
+        let mut state = self.lock();
+        match state.deadline(cfg) {
+            Some(at) if at <= now && state.pending => (true, false),
+            Some(at) if at <= now => {
+                state.moved_at = None;
+                (false, true)
+            }
+            _ => (false, false),
+        }
+    }
+}
+
+impl ThrottleState {
+    /// Rate limit expiry if a change is waiting, else the time the BPM counts as settled
+    fn deadline(&self, cfg: &HttpSettings) -> Option<Instant> {
+        if self.pending {
+            self.sent_at.map(|at| at + Duration::from_millis(cfg.bpm_rate_limit))
+        } else {
+            self.moved_at.map(|at| at + Duration::from_millis(cfg.bpm_settle_time))
+        }
+    }
+}
+
+/// Push the master clock changes that waited for the rate limit, and the settled events, until the relay shuts down
+pub fn spawn(app: Arc<App>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let cfg = &app.settings().http;
+            let deadline = app.bpm_throttle.deadline(cfg);
+            let sleep = async {
+                match deadline {
+                    Some(at) => tokio::time::sleep_until(at.into()).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = sleep => {
+                    let (retry, settled) = app.bpm_throttle.take_due(cfg, Instant::now());
+                    // Like the HTTP handlers, the state lock is taken on the blocking pool
+                    let flush_app = app.clone();
+                    match tokio::task::spawn_blocking(move || http_server::flush_bpm(&flush_app, retry, settled)).await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => error!("Could not push the master clock: {}", e),
+                        Err(e) => error!("Master clock push panicked: {}", e),
+                    }
+                },
+                _ = app.bpm_throttle.wake.notified() => {},
+                _ = app.shutdown_requested() => break,
+            }
+        }
+        debug!("BPM throttle stopped");
+    })
+}
@@ -1,73 +1,290 @@
-use super::settings;
-use futures_channel::mpsc::{unbounded, UnboundedSender};
-use futures_util::{future, pin_mut, stream::TryStreamExt, StreamExt};
-use std::collections::HashMap;
+use super::{
+    app::App,
+    commands,
+    error::{RelayError, Result},
+    settings::SlowClientPolicy,
+};
+use futures_util::{stream::SplitSink, SinkExt, StreamExt};
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
-use std::thread::spawn;
+use std::time::{Duration, Instant};
 use tokio::net::{TcpListener, TcpStream};
-use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::protocol::{frame::coding::CloseCode, CloseFrame, Message};
 
-type Tx = UnboundedSender<Message>;
-type PeerMap = Arc<RwLock<HashMap<SocketAddr, Tx>>>;
+/// How long to wait for the clients to acknowledge the close frame on shutdown
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(3);
 
-pub fn spawn_ws() {
-    spawn(|| {
-        ws_server();
-    });
+/// Kinds of messages pushed to the websocket clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Topic {
+    /// Tracks on air have changed
+    NowPlaying,
+    /// Minor update of a track on air
+    Tick,
+    /// Master clock has changed
+    Bpm,
+    /// Master clock has stopped moving
+    BpmSettled,
+    /// Settings were reloaded
+    ConfigChanged,
+    /// Traktor went silent or came back
+    TraktorConnectionChanged,
+    /// Metadata override of a deck was changed
+    OverrideChanged,
+    /// Keys or tempos of the loaded decks have changed how they mix
+    MixHints,
 }
 
-lazy_static! {
-    static ref SUBSCRIBERS: PeerMap = PeerMap::new(RwLock::new(HashMap::new()));
+/// A connected websocket client
+pub struct Peer {
+    /// Messages waiting to be sent to the client
+    tx: mpsc::Sender<Message>,
+    /// Asks the connection to close because the client does not keep up
+    kick: Arc<Notify>,
+    /// Topics the client has subscribed to, all of them if `None`
+    topics: Option<HashSet<Topic>>,
 }
 
-#[tokio::main]
-async fn ws_server() {
-    let cfg = &settings::ServerSettings::shared().http;
+pub type PeerMap = RwLock<HashMap<SocketAddr, Peer>>;
+type WsSink = SplitSink<WebSocketStream<TcpStream>, Message>;
+
+/// Bind the websocket server and serve it on the current runtime until the relay shuts down.
+/// Returns the bound address and the server task.
+pub fn start(app: Arc<App>) -> Result<(SocketAddr, JoinHandle<()>)> {
+    let cfg = &app.settings().http;
     let host = &cfg.bind;
     let port = &cfg.ws_port;
-    info!("Start WS at {}:{}", host, port);
 
-    let try_socket = TcpListener::bind(format!("{}:{}", host, port)).await;
-    let listener = try_socket.expect("Failed to bind");
+    let listener = std::net::TcpListener::bind(format!("{}:{}", host, port))?;
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+    let addr = listener.local_addr()?;
+    info!("Start WS at {}", addr);
+
+    let task = tokio::spawn(ws_server(app, listener));
+    Ok((addr, task))
+}
 
-    while let Ok((stream, addr)) = listener.accept().await {
-        tokio::spawn(handle_connection(stream, addr));
+async fn ws_server(app: Arc<App>, listener: TcpListener) {
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, addr)) => {
+                    let app = app.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_connection(&app, stream, addr).await {
+                            warn!("WS connection {} failed: {}", addr, e);
+                        }
+                        remove_peer(&app, &addr);
+                    });
+                }
+                Err(e) => error!("Could not accept WS connection: {}", e),
+            },
+            _ = app.shutdown_requested() => break,
+        }
     }
+    debug!("WS server stopped");
 }
 
-async fn handle_connection(raw_stream: TcpStream, addr: SocketAddr) {
-    println!("Incoming TCP connection from: {}", addr);
+async fn handle_connection(app: &Arc<App>, raw_stream: TcpStream, addr: SocketAddr) -> Result<()> {
+    info!("Incoming TCP connection from: {}", addr);
+
+    let ws_stream = tokio_tungstenite::accept_async(raw_stream).await?;
+    info!("WebSocket connection established: {}", addr);
+
+    let cfg = &app.settings().http;
+    let ping_interval = Duration::from_secs(cfg.ws_ping_interval);
+    let pong_timeout = Duration::from_secs(cfg.ws_pong_timeout);
+
+    let (tx, mut rx) = mpsc::channel(cfg.ws_queue_size);
+    let replies = tx.clone();
+    let kick = Arc::new(Notify::new());
+    app.subscribers.write()?.insert(addr, Peer { tx, kick: kick.clone(), topics: None });
 
-    let ws_stream = tokio_tungstenite::accept_async(raw_stream)
-        .await
-        .expect("Error during the websocket handshake occurred");
-    println!("WebSocket connection established: {}", addr);
+    let (mut outgoing, mut incoming) = ws_stream.split();
 
-    let (tx, rx) = unbounded();
-    SUBSCRIBERS.write().unwrap().insert(addr, tx);
+    let mut ping = tokio::time::interval_at(tokio::time::Instant::now() + ping_interval, ping_interval);
+    let mut last_seen = Instant::now();
+    // Once the close frame is sent, only wait for the client to acknowledge it
+    let mut closing = false;
 
-    let (outgoing, incoming) = ws_stream.split();
+    loop {
+        let close_frame = tokio::select! {
+            received = incoming.next() => match received {
+                None => break,
+                Some(received) => {
+                    last_seen = Instant::now();
+                    match received? {
+                        Message::Close(_) if !closing => debug!("{} is closing the connection", addr),
+                        Message::Text(text) if !closing => {
+                            tokio::spawn(run_command(app.clone(), addr, text, replies.clone()));
+                        }
+                        _ => {}
+                    }
+                    None
+                }
+            },
+            queued = rx.recv(), if !closing => match queued {
+                Some(msg) => {
+                    if !send(&mut outgoing, msg, pong_timeout).await? {
+                        warn!("WS client {} stopped receiving, disconnecting", addr);
+                        app.metrics.count_ws_reaped();
+                        break;
+                    }
+                    None
+                }
+                None => break,
+            },
+            _ = ping.tick() => {
+                if last_seen.elapsed() >= pong_timeout {
+                    warn!("WS client {} did not answer for {:?}, disconnecting", addr, last_seen.elapsed());
+                    app.metrics.count_ws_reaped();
+                    break;
+                }
+                if !closing {
+                    trace!("Ping WS client {}", addr);
+                    if !send(&mut outgoing, Message::Ping(vec![]), pong_timeout).await? {
+                        warn!("WS client {} stopped receiving, disconnecting", addr);
+                        app.metrics.count_ws_reaped();
+                        break;
+                    }
+                }
+                None
+            },
+            _ = kick.notified(), if !closing => {
+                warn!("WS client {} is too slow, disconnecting", addr);
+                app.metrics.count_ws_reaped();
+                Some(CloseFrame { code: CloseCode::Policy, reason: "Client is too slow".into() })
+            },
+            _ = app.shutdown_requested(), if !closing => {
+                Some(CloseFrame { code: CloseCode::Away, reason: "Relay is shutting down".into() })
+            },
+        };
 
-    let handle_incoming = incoming.try_for_each(|_| future::ok(()));
+        if let Some(frame) = close_frame {
+            closing = true;
+            if !send(&mut outgoing, Message::Close(Some(frame)), pong_timeout).await? {
+                break;
+            }
+        }
+    }
+
+    info!("{} disconnected", &addr);
+    Ok(())
+}
 
-    let receive_from_others = rx.map(Ok).forward(outgoing);
+/// Run a command off the connection loop, which keeps the broadcasts and pings going meanwhile,
+/// and queue the reply like the broadcasts
+async fn run_command(app: Arc<App>, addr: SocketAddr, text: String, replies: mpsc::Sender<Message>) {
+    // Commands take the state lock, which must not block the runtime
+    let command_app = app.clone();
+    let reply = match tokio::task::spawn_blocking(move || commands::handle(&command_app, addr, &text)).await {
+        Ok(Ok(reply)) => reply,
+        Ok(Err(e)) => {
+            error!("Could not answer WS command from {}: {}", addr, e);
+            return;
+        }
+        Err(e) => {
+            error!("WS command from {} panicked: {}", addr, e);
+            return;
+        }
+    };
+    let reply_len = reply.len();
+    // The reply waits for room in the queue rather than being dropped like a broadcast
+    if replies.send(Message::Text(reply)).await.is_ok() {
+        app.metrics.count_ws_sent(reply_len);
+    }
+}
 
-    pin_mut!(handle_incoming, receive_from_others);
-    future::select(handle_incoming, receive_from_others).await;
+/// Send a message to the client, giving up if it does not take it within `timeout`.
+/// Returns whether the message was sent.
+async fn send(outgoing: &mut WsSink, msg: Message, timeout: Duration) -> Result<bool> {
+    match tokio::time::timeout(timeout, outgoing.send(msg)).await {
+        Ok(sent) => {
+            sent?;
+            Ok(true)
+        }
+        Err(_) => Ok(false),
+    }
+}
+
+fn remove_peer(app: &App, addr: &SocketAddr) {
+    match app.subscribers.write() {
+        Ok(mut peers) => {
+            peers.remove(addr);
+        }
+        Err(e) => error!("Could not remove WS peer {}: {}", addr, RelayError::from(e)),
+    }
+}
+
+/// Wait for the clients to disconnect after the shutdown was requested
+pub async fn close_all(app: &App) {
+    let deadline = Instant::now() + CLOSE_TIMEOUT;
+    while peer_count(app) > 0 && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    if peer_count(app) > 0 {
+        warn!("{} WS client(s) did not acknowledge the close frame", peer_count(app));
+    }
+}
+
+/// Choose the topics pushed to the client at `addr`, all of them if `None`
+pub fn set_topics(app: &App, addr: &SocketAddr, topics: Option<HashSet<Topic>>) -> Result<()> {
+    if let Some(peer) = app.subscribers.write()?.get_mut(addr) {
+        debug!("WS client {} subscribed to {:?}", addr, topics);
+        peer.topics = topics;
+    }
+    Ok(())
+}
 
-    println!("{} disconnected", &addr);
-    SUBSCRIBERS.write().unwrap().remove(&addr);
+/// Number of connected websocket clients
+pub fn peer_count(app: &App) -> usize {
+    app.subscribers.read().map(|peers| peers.len()).unwrap_or_default()
 }
 
-pub fn ws_push(msg: &impl serde::Serialize) {
-    let ser = serde_json::to_string(msg).unwrap();
+/// Send the message to every client subscribed to the topic
+pub fn ws_push(app: &App, topic: Topic, msg: &impl serde::Serialize) {
+    let ser = match serde_json::to_string(msg) {
+        Ok(ser) => ser,
+        Err(e) => {
+            error!("Could not serialize WS msg: {}", e);
+            return;
+        }
+    };
     info!("Broadcast WS msg: {}", ser);
-    let peers = SUBSCRIBERS.read().unwrap();
 
-    let broadcast_recipients = peers.iter().map(|(_, ws_sink)| ws_sink);
+    let peers = match app.subscribers.read() {
+        Ok(peers) => peers,
+        Err(e) => {
+            error!("Could not broadcast WS msg: {}", RelayError::from(e));
+            return;
+        }
+    };
 
-    for recp in broadcast_recipients {
-        recp.unbounded_send(Message::Text(ser.clone())).unwrap();
+    let policy = app.settings().http.ws_slow_client;
+    app.metrics.count_ws_broadcast();
+    for (addr, peer) in peers.iter() {
+        if let Some(topics) = &peer.topics {
+            if !topics.contains(&topic) {
+                continue;
+            }
+        }
+        match peer.tx.try_send(Message::Text(ser.clone())) {
+            Ok(()) => app.metrics.count_ws_sent(ser.len()),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                app.metrics.count_ws_dropped();
+                match policy {
+                    SlowClientPolicy::Drop => debug!("Queue of WS client {} is full, dropping msg", addr),
+                    SlowClientPolicy::Disconnect => peer.kick.notify_one(),
+                }
+            }
+            // Peer that is going away will be removed by its own connection task
+            Err(mpsc::error::TrySendError::Closed(_)) => debug!("WS client {} is going away", addr),
+        }
     }
 }
@@ -0,0 +1,516 @@
+//! HTTP API behaviour as seen by Traktor and the widgets
+mod common;
+
+use common::{track, Relay};
+use serde_json::json;
+use std::time::Duration;
+use traktor_obs_relay::http_server;
+
+#[test]
+fn now_playing_is_empty_at_start() {
+    let relay = Relay::start();
+    let resp = relay.get("/nowPlaying");
+    assert_eq!(resp.status, 200);
+    let np = resp.json();
+    assert_eq!(np["bpm"], 0.0);
+    assert_eq!(np["songsOnAir"], json!([]));
+    assert_eq!(np["tickedDeck"], json!(null));
+}
+
+#[test]
+fn loaded_deck_is_on_air() {
+    let relay = Relay::start();
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+
+    let np = relay.get("/nowPlaying").json();
+    let songs = np["songsOnAir"].as_array().unwrap();
+    assert_eq!(songs.len(), 1);
+    assert_eq!(songs[0]["title"], "Rise");
+    assert_eq!(songs[0]["artist"], "Someone");
+    assert_eq!(songs[0]["deck"], "A");
+}
+
+#[test]
+fn muted_channel_hides_its_deck() {
+    let relay = Relay::start();
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    relay.traktor("/deckLoaded/B", track("Fall", "Someone Else"));
+    relay.traktor("/updateChannel/1", json!({ "isOnAir": false }));
+
+    let np = relay.get("/nowPlaying").json();
+    let songs = np["songsOnAir"].as_array().unwrap();
+    assert_eq!(songs.len(), 1);
+    assert_eq!(songs[0]["deck"], "B");
+}
+
+#[test]
+fn decks_outside_deck_list_are_ignored() {
+    let relay = Relay::start();
+    relay.traktor("/deckLoaded/E", track("Hidden", "Nobody"));
+    assert_eq!(relay.get("/nowPlaying").json()["songsOnAir"], json!([]));
+}
+
+#[test]
+fn deck_updates_apply_to_loaded_track() {
+    let relay = Relay::start();
+    relay.traktor("/deckLoaded/C", track("Rise", "Someone"));
+    relay.traktor("/updateDeck/C", json!({ "isPlaying": true, "elapsedTime": 12.5, "tempo": 1.02 }));
+
+    let np = relay.get("/nowPlaying").json();
+    let deck = &np["songsOnAir"][0];
+    assert_eq!(deck["isPlaying"], true);
+    assert_eq!(deck["elapsedTime"], 12.5);
+    assert_eq!(deck["tempo"].as_f64().unwrap() as f32, 1.02);
+}
+
+#[test]
+fn master_clock_sets_bpm() {
+    let relay = Relay::start();
+    relay.traktor("/updateMasterClock", json!({ "deck": "A", "bpm": 124.5 }));
+    assert_eq!(relay.get("/nowPlaying").json()["bpm"], 124.5);
+}
+
+#[test]
+fn malformed_body_is_bad_request() {
+    let relay = Relay::start();
+    let resp = relay.post("/deckLoaded/A", "{ not json");
+    assert_eq!(resp.status, 400);
+    assert_eq!(resp.json()["code"], "badRequest");
+
+    let resp = relay.post("/updateChannel/1", r#"{ "isOnAir": "yes" }"#);
+    assert_eq!(resp.status, 400);
+    assert_eq!(resp.json()["code"], "badRequest");
+}
+
+#[test]
+fn missing_subtitles_are_not_found() {
+    let relay = Relay::start();
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    let resp = relay.get("/subtitles/A");
+    assert_eq!(resp.status, 404);
+    assert_eq!(resp.json()["code"], "notFound");
+}
+
+#[test]
+fn filename_of_loaded_track() {
+    let relay = Relay::start();
+    relay.traktor("/deckLoaded/B", track("Rise", "Someone"));
+    let resp = relay.get("/filename/B");
+    assert_eq!(resp.status, 200);
+    assert_eq!(resp.body, "Someone - Rise");
+}
+
+#[test]
+fn status_lists_decks() {
+    let relay = Relay::start();
+    let status = relay.get("/status").json();
+    assert_eq!(status["decks"], json!(["A", "B", "C", "D"]));
+    assert_eq!(status["wsPeers"], 0);
+    assert_eq!(status["version"], env!("CARGO_PKG_VERSION"));
+}
+
+#[test]
+fn reload_applies_settings_and_reports_restart_required() {
+    let path = common::temp_path("reload.toml");
+    std::fs::write(&path, common::TEST_SETTINGS).unwrap();
+    let relay = Relay::start_from_file(&path);
+    let mut ws = relay.subscribe();
+
+    let changed = common::settings_with(&[
+        ("http", "port", "1"),
+        ("mixing", "deck_list", r#"["A", "B"]"#),
+        ("mixing", "deck_channel_map", "{ A = 1, B = 2 }"),
+    ]);
+    std::fs::write(&path, changed).unwrap();
+    let resp = relay.post("/admin/reload", "");
+    assert_eq!(resp.status, 204, "{}", resp.body);
+    let event = ws.next_matching(|msg| msg["event"] == "configChanged");
+    assert_eq!(event["restartRequired"], json!(["http.port"]));
+    assert_eq!(relay.app.settings().mixing.deck_list, ["A", "B"]);
+    assert_eq!(relay.app.settings().http.port, 0);
+
+    // A broken file is refused and the running settings are kept
+    std::fs::write(&path, "[mixing]\ndeck_list = \"A\"\n").unwrap();
+    assert_eq!(relay.post("/admin/reload", "").status, 400);
+    assert_eq!(relay.app.settings().mixing.deck_list, ["A", "B"]);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn modified_settings_file_is_reloaded_until_shutdown() {
+    let path = common::temp_path("watched.toml");
+    std::fs::write(&path, common::TEST_SETTINGS).unwrap();
+    let relay = Relay::start_from_file(&path);
+    {
+        let _runtime = relay.runtime.enter();
+        relay.app.spawn(http_server::spawn_settings_watcher(relay.app.clone()));
+    }
+    // Let the watcher see the file as it was, modification times may only count seconds
+    std::thread::sleep(Duration::from_millis(1100));
+
+    let changed = common::settings_with(&[
+        ("mixing", "deck_list", r#"["A", "B"]"#),
+        ("mixing", "deck_channel_map", "{ A = 1, B = 2 }"),
+    ]);
+    std::fs::write(&path, changed).unwrap();
+    common::wait_until(|| relay.app.settings().mixing.deck_list == ["A", "B"]);
+
+    // The watcher stops with the relay
+    let started = std::time::Instant::now();
+    relay.runtime.block_on(relay.begin_shutdown()).unwrap();
+    assert!(started.elapsed() < Duration::from_secs(1), "{:?}", started.elapsed());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn relays_do_not_share_state() {
+    let first = Relay::start();
+    let second = Relay::start();
+    first.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    assert_eq!(second.get("/nowPlaying").json()["songsOnAir"], json!([]));
+}
+
+#[test]
+fn traktor_activity_is_tracked_per_relay() {
+    let first = Relay::start();
+    let second = Relay::start();
+    first.traktor("/updateChannel/1", json!({ "isOnAir": true }));
+    assert_eq!(first.get("/status").json()["traktorConnected"], true);
+    assert_eq!(second.get("/status").json()["traktorConnected"], false);
+}
+
+#[test]
+fn assets_are_served_from_webroot() {
+    let relay = Relay::start();
+    let resp = relay.get("/index.html");
+    assert_eq!(resp.status, 200);
+    assert!(resp.body.contains("<html"));
+
+    assert_eq!(relay.get("/nothing-here.html").status, 404);
+    assert_eq!(relay.get("/../config.toml").status, 404);
+    assert_eq!(relay.get("/%2E%2E/config.toml").status, 404);
+}
+
+#[test]
+fn deck_override_lasts_until_next_load() {
+    let relay = Relay::start();
+    relay.traktor("/deckLoaded/A", track("Track 03", "Unknown Artist"));
+    let resp = relay.post("/override/A", r#"{ "title": "Real Title", "artist": "Real Artist" }"#);
+    assert_eq!(resp.status, 200, "{}", resp.body);
+    assert_eq!(resp.json()["deck"]["title"], "Real Title");
+
+    let song = &relay.get("/nowPlaying").json()["songsOnAir"][0];
+    assert_eq!(song["title"], "Real Title");
+    assert_eq!(song["artist"], "Real Artist");
+    assert_eq!(song["genre"], "Techno");
+
+    relay.traktor("/deckLoaded/A", track("Track 03", "Unknown Artist"));
+    assert_eq!(relay.get("/nowPlaying").json()["songsOnAir"][0]["title"], "Track 03");
+    assert_eq!(relay.get("/override/A").json(), json!({ "deck": null, "file": null }));
+}
+
+#[test]
+fn file_override_is_kept_in_overrides_file() {
+    let path = common::temp_path("overrides.json");
+    let relay = Relay::start_with(&common::settings_with(&[("mixing", "overrides_file", &format!("{:?}", path))]));
+    relay.traktor("/deckLoaded/B", track("Promo", "White Label"));
+    let resp = relay.post("/override/B", r#"{ "scope": "file", "title": "Unreleased" }"#);
+    assert_eq!(resp.status, 200, "{}", resp.body);
+
+    // Loading the same file onto another deck keeps the override
+    relay.traktor("/deckLoaded/C", track("Promo", "White Label"));
+    let songs = relay.get("/nowPlaying").json()["songsOnAir"].clone();
+    assert_eq!(songs[0]["title"], "Unreleased");
+    assert_eq!(songs[1]["title"], "Unreleased");
+    assert_eq!(songs[1]["artist"], "White Label");
+
+    // And so does starting the relay again
+    drop(relay);
+    let relay = Relay::start_with(&common::settings_with(&[("mixing", "overrides_file", &format!("{:?}", path))]));
+    relay.traktor("/deckLoaded/A", track("Promo", "White Label"));
+    assert_eq!(relay.get("/nowPlaying").json()["songsOnAir"][0]["title"], "Unreleased");
+
+    // Removing it takes an explicit clear
+    let resp = relay.post("/override/A", r#"{ "scope": "file", "clear": true }"#);
+    assert_eq!(resp.status, 200, "{}", resp.body);
+    assert_eq!(resp.json()["file"], json!(null));
+    assert_eq!(relay.get("/nowPlaying").json()["songsOnAir"][0]["title"], "Promo");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn override_typos_are_rejected() {
+    let relay = Relay::start();
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    relay.post("/override/A", r#"{ "title": "Real Title", "comment2": "Real Comment" }"#);
+
+    assert_eq!(relay.post("/override/A", r#"{ "titel": "Typo" }"#).status, 400);
+    assert_eq!(relay.post("/override/A", r#"{}"#).status, 400);
+    assert_eq!(relay.post("/override/A", r#"{ "clear": true, "title": "Both" }"#).status, 400);
+    let song = &relay.get("/nowPlaying").json()["songsOnAir"][0];
+    assert_eq!(song["title"], "Real Title");
+    assert_eq!(song["comment2"], "Real Comment");
+
+    let resp = relay.request("DELETE", "/override/A", None);
+    assert_eq!(resp.status, 200, "{}", resp.body);
+    assert_eq!(resp.json()["deck"], json!(null));
+    assert_eq!(relay.get("/nowPlaying").json()["songsOnAir"][0]["title"], "Rise");
+}
+
+#[test]
+fn override_of_empty_deck_is_rejected() {
+    let relay = Relay::start();
+    let resp = relay.post("/override/A", r#"{ "title": "Nothing" }"#);
+    assert_eq!(resp.status, 404);
+    assert_eq!(resp.json()["code"], "unknownDeck");
+}
+
+#[test]
+fn hidden_deck_shows_id_and_no_files() {
+    let relay = Relay::start();
+    relay.traktor("/deckLoaded/B", track("Rise", "Someone"));
+    let resp = relay.post("/override/B", r#"{ "hidden": true }"#);
+    assert_eq!(resp.status, 200, "{}", resp.body);
+
+    let song = &relay.get("/nowPlaying").json()["songsOnAir"][0];
+    assert_eq!(song["title"], "ID");
+    assert_eq!(song["artist"], "ID");
+    assert_eq!(song["isHidden"], true);
+    assert_eq!(relay.get("/filename/B").status, 404);
+    assert_eq!(relay.get("/video/B").status, 404);
+    // Default cover, so that the widgets still have something to show
+    assert_eq!(relay.get("/artwork/B").status, 200);
+
+    relay.post("/override/B", r#"{ "hidden": false }"#);
+    assert_eq!(relay.get("/nowPlaying").json()["songsOnAir"][0]["title"], "Rise");
+    assert_eq!(relay.get("/filename/B").body, "Someone - Rise");
+}
+
+#[test]
+fn deck_override_reveals_marked_track() {
+    let relay = Relay::start();
+    let mut promo = track("Rise", "Someone");
+    promo["comment"] = json!("[ID]");
+    relay.traktor("/deckLoaded/A", promo);
+    assert_eq!(relay.get("/nowPlaying").json()["songsOnAir"][0]["title"], "ID");
+
+    relay.post("/override/A", r#"{ "hidden": false }"#);
+    assert_eq!(relay.get("/nowPlaying").json()["songsOnAir"][0]["title"], "Rise");
+}
+
+#[test]
+fn traktor_cannot_hide_a_track() {
+    let relay = Relay::start();
+    let mut sent = track("Rise", "Someone");
+    sent["isHidden"] = json!(true);
+    relay.traktor("/deckLoaded/A", sent);
+    let song = &relay.get("/nowPlaying").json()["songsOnAir"][0];
+    assert_eq!(song["isHidden"], false);
+    assert_eq!(song["title"], "Rise");
+}
+
+#[test]
+fn loaded_track_is_cleaned_up() {
+    let settings = format!("{}\n{}", common::TEST_SETTINGS, r#"
+        [[cleanup]]
+        rule = "splitArtist"
+        "#);
+    let relay = Relay::start_with(&settings);
+    relay.traktor("/deckLoaded/A", track("Someone - Rise", ""));
+    let song = &relay.get("/nowPlaying").json()["songsOnAir"][0];
+    assert_eq!(song["artist"], "Someone");
+    assert_eq!(song["title"], "Rise");
+    assert_eq!(song["original"], json!({ "title": "Someone - Rise", "artist": "" }));
+}
+
+#[test]
+fn traktor_cannot_set_cleanup_results() {
+    let relay = Relay::start();
+    let mut sent = track("Rise", "Someone");
+    sent["featuring"] = json!("Nobody");
+    sent["original"] = json!({ "title": "Something Else" });
+    relay.traktor("/deckLoaded/A", sent);
+    let song = &relay.get("/nowPlaying").json()["songsOnAir"][0];
+    assert_eq!(song["featuring"], "");
+    assert!(song.get("original").is_none(), "{}", song);
+}
+
+#[test]
+fn keys_are_sent_in_every_notation() {
+    let relay = Relay::start();
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    relay.traktor("/updateDeck/A", json!({ "resultingKey": "2m" }));
+    let song = &relay.get("/nowPlaying").json()["songsOnAir"][0];
+    assert_eq!(song["keyNotation"], json!({ "camelot": "8A", "openKey": "1m", "musical": "Am", "name": "A minor" }));
+    assert_eq!(song["resultingKeyNotation"]["camelot"], "9A");
+    assert_eq!(song["resultingKeyNotation"]["musical"], "Em");
+}
+
+#[test]
+fn mix_hints_compare_loaded_decks() {
+    let relay = Relay::start();
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    assert_eq!(relay.get("/mixHints").json(), json!([]));
+
+    let mut other = track("Fall", "Someone Else");
+    other["bpm"] = json!(120.0);
+    other["key"] = json!("2d");
+    other["resultingKey"] = json!("2d");
+    relay.traktor("/deckLoaded/C", other);
+
+    let hints = relay.get("/mixHints").json();
+    assert_eq!(hints[0]["from"], "A");
+    assert_eq!(hints[0]["to"], "C");
+    assert_eq!(hints[0]["keyRelation"], "clash");
+    assert_eq!(hints[0]["bpmDifference"], -8.0);
+    let pitch = hints[0]["pitch"].as_f64().unwrap();
+    assert!((pitch - 6.6667).abs() < 0.001, "{}", pitch);
+}
+
+#[test]
+fn decks_know_the_master_clock() {
+    let relay = Relay::start();
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    relay.traktor("/deckLoaded/B", track("Fall", "Someone"));
+    relay.traktor("/updateDeck/B", json!({ "tempo": 0.9 }));
+    relay.traktor("/updateMasterClock", json!({ "deck": "A", "bpm": 128.0 }));
+
+    let songs = relay.get("/nowPlaying").json()["songsOnAir"].clone();
+    assert_eq!(songs[0]["effectiveBpm"], 128.0);
+    assert_eq!(songs[0]["isMaster"], true);
+    assert_eq!(songs[0]["matchesMaster"], true);
+    assert_eq!(songs[1]["isMaster"], false);
+    assert_eq!(songs[1]["matchesMaster"], false);
+    assert!((songs[1]["effectiveBpm"].as_f64().unwrap() - 115.2).abs() < 0.001);
+}
+
+#[test]
+fn plays_and_airtime_are_counted() {
+    let relay = Relay::start();
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    assert_eq!(relay.get("/nowPlaying").json()["songsOnAir"][0]["stats"], json!(null));
+    relay.traktor("/updateDeck/A", json!({ "isPlaying": true }));
+    std::thread::sleep(std::time::Duration::from_millis(300));
+    relay.traktor("/updateDeck/A", json!({ "elapsedTime": 0.3 }));
+
+    let stats = relay.get("/stats/track?path=%2Fmusic%2FSomeone+-+Rise.mp3").json();
+    assert_eq!(stats["playCount"], 1);
+    assert_eq!(stats["artist"], "Someone");
+    assert!(stats["airtime"].as_f64().unwrap() >= 0.25, "{}", stats);
+    assert!(stats["lastPlayed"].as_f64().is_some());
+    assert_eq!(relay.get("/nowPlaying").json()["songsOnAir"][0]["stats"]["playCount"], 1);
+
+    // Loading the file again makes another play
+    relay.traktor("/deckLoaded/B", track("Fall", "Someone Else"));
+    relay.traktor("/updateDeck/B", json!({ "isPlaying": true }));
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    relay.traktor("/updateDeck/A", json!({ "isPlaying": true }));
+    let top = relay.get("/stats/top?limit=1").json();
+    assert_eq!(top, json!([relay.get("/stats/track?artist=someone&title=RISE").json()]));
+    assert_eq!(top[0]["path"], "/music/Someone - Rise.mp3");
+    assert_eq!(top[0]["playCount"], 2);
+
+    assert_eq!(relay.get("/stats/track?path=nothing").status, 404);
+    assert_eq!(relay.get("/stats/track").status, 400);
+    assert_eq!(relay.get("/stats/top?by=mood").status, 400);
+}
+
+#[test]
+fn hidden_tracks_are_not_counted() {
+    let relay = Relay::start();
+    let mut promo = track("Rise", "Someone");
+    promo["comment"] = json!("[ID]");
+    relay.traktor("/deckLoaded/A", promo);
+    relay.traktor("/updateDeck/A", json!({ "isPlaying": true }));
+    assert_eq!(relay.get("/stats/top").json(), json!([]));
+}
+
+#[test]
+fn stats_are_kept_in_stats_file() {
+    let path = common::temp_path("stats.json");
+    let settings = common::settings_with(&[("mixing", "stats_file", &format!("{:?}", path))]);
+    let relay = Relay::start_with(&settings);
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    relay.traktor("/updateDeck/A", json!({ "isPlaying": true }));
+    drop(relay);
+
+    let relay = Relay::start_with(&settings);
+    assert_eq!(relay.get("/stats/top").json()[0]["title"], "Rise");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn airtime_is_saved_while_playing() {
+    let path = common::temp_path("airtime.json");
+    let settings = common::settings_with(&[
+        ("mixing", "stats_file", &format!("{:?}", path)),
+        ("mixing", "stats_save_interval", "1"),
+    ]);
+    let relay = Relay::start_with(&settings);
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    relay.traktor("/updateDeck/A", json!({ "isPlaying": true }));
+    std::thread::sleep(std::time::Duration::from_millis(300));
+    relay.traktor("/updateDeck/A", json!({ "elapsedTime": 0.3 }));
+
+    // No play is counted by the update, the airtime is saved in the background
+    let saved_airtime = || {
+        let saved: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap_or_default()).unwrap_or_default();
+        saved["/music/Someone - Rise.mp3"]["airtime"].as_f64().unwrap_or_default()
+    };
+    common::wait_until(|| saved_airtime() >= 0.25);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn output_files_follow_the_mix() {
+    let text_path = common::temp_path("now_playing.txt");
+    let art_path = common::temp_path("artwork.jpg");
+    let settings = format!(
+        "{}\n[outputs]\nartwork = {:?}\n[outputs.files]\n{:?} = \"%artist% – %title%\"\n",
+        common::TEST_SETTINGS, art_path, text_path
+    );
+    let relay = Relay::start_with(&settings);
+    let read = || std::fs::read_to_string(&text_path).unwrap_or_default();
+
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    common::wait_until(|| read() == "Someone – Rise");
+    assert_eq!(std::fs::read(&art_path).unwrap(), std::fs::read("./assets/default.jpg").unwrap());
+
+    // The track that started playing last wins
+    relay.traktor("/deckLoaded/B", track("Fall", "Someone Else"));
+    relay.traktor("/updateDeck/A", json!({ "isPlaying": true }));
+    relay.traktor("/updateDeck/B", json!({ "isPlaying": true }));
+    common::wait_until(|| read() == "Someone Else – Fall");
+    relay.traktor("/updateChannel/2", json!({ "isOnAir": false }));
+    common::wait_until(|| read() == "Someone – Rise");
+
+    let _ = std::fs::remove_file(&text_path);
+    let _ = std::fs::remove_file(&art_path);
+}
+
+#[test]
+fn master_clock_is_written_to_the_files_showing_it() {
+    let path = common::temp_path("bpm.txt");
+    let settings = format!("{}\n[outputs.files]\n{:?} = \"%bpm% on %masterDeck%\"\n", common::TEST_SETTINGS, path);
+    let relay = Relay::start_with(&settings);
+    let read = || std::fs::read_to_string(&path).unwrap_or_default();
+
+    relay.traktor("/updateMasterClock", json!({ "deck": "A", "bpm": 124.0 }));
+    common::wait_until(|| read() == "124.00 on A");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn templates_are_rendered_as_text() {
+    let settings = format!("{}\n[templates]\nnowPlaying = \"%artist% — %title%\"\n", common::TEST_SETTINGS);
+    let relay = Relay::start_with(&settings);
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+
+    let resp = relay.get("/render/nowPlaying");
+    assert_eq!(resp.status, 200);
+    assert_eq!(resp.body, "Someone — Rise");
+    assert_eq!(relay.get("/render?template=%7Bartist%7D%20on%20%25deck%25").body, "Someone on A");
+
+    assert_eq!(relay.get("/render/missing").status, 404);
+    assert_eq!(relay.get("/render").status, 400);
+}
@@ -0,0 +1,98 @@
+//! Metadata cleanup rules read from the settings format
+mod common;
+
+use traktor_obs_relay::{
+    api::deck::{DeckStatus, TrackField},
+    cleanup,
+    settings::ServerSettings,
+};
+
+fn clean(rules: &str, title: &str, artist: &str) -> DeckStatus {
+    let settings = ServerSettings::from_toml(&format!("{}\n{}", common::TEST_SETTINGS, rules)).unwrap();
+    assert!(settings.validate().is_empty(), "{:?}", settings.validate());
+    let mut track: DeckStatus = serde_json::from_value(common::track(title, artist)).unwrap();
+    cleanup::apply(&settings.cleanup, &mut track);
+    track
+}
+
+#[test]
+fn artist_is_split_out_of_title() {
+    let track = clean(
+        r#"
+        [[cleanup]]
+        rule = "splitArtist"
+        "#,
+        "Someone - Rise",
+        "",
+    );
+    assert_eq!(track.artist, "Someone");
+    assert_eq!(track.title, "Rise");
+    assert_eq!(track.original[&TrackField::Title], "Someone - Rise");
+}
+
+#[test]
+fn junk_is_removed_in_order() {
+    let track = clean(
+        r#"
+        [[cleanup]]
+        rule = "replace"
+        fields = ["title"]
+        pattern = '\[(FREE DL|[A-Z]+\d+)\]'
+
+        [[cleanup]]
+        rule = "stripSuffix"
+        suffixes = ["(Original Mix)", "(Extended Mix)"]
+
+        [[cleanup]]
+        rule = "case"
+        fields = ["artist"]
+        case = "title"
+        "#,
+        "Rise [FREE DL] (original mix) [CAT042]",
+        "SOMEONE ELSE",
+    );
+    assert_eq!(track.title, "Rise");
+    assert_eq!(track.artist, "Someone Else");
+    assert_eq!(track.original.len(), 2);
+}
+
+#[test]
+fn featured_artists_are_extracted() {
+    let track = clean(
+        r#"
+        [[cleanup]]
+        rule = "extractFeat"
+        "#,
+        "Rise (feat. Vocalist) (Club Mix)",
+        "Someone ft. Rapper",
+    );
+    assert_eq!(track.title, "Rise (Club Mix)");
+    assert_eq!(track.artist, "Someone");
+    assert_eq!(track.featuring, "Vocalist, Rapper");
+}
+
+#[test]
+fn no_rules_keep_track_as_is() {
+    let track = clean("", "Rise (Original Mix)", "");
+    assert_eq!(track.title, "Rise (Original Mix)");
+    assert!(track.original.is_empty());
+}
+
+#[test]
+fn broken_rules_are_reported() {
+    let rules = r#"
+        [[cleanup]]
+        rule = "replace"
+        pattern = "(unclosed"
+        "#;
+    assert!(ServerSettings::from_toml(&format!("{}\n{}", common::TEST_SETTINGS, rules)).is_err());
+
+    let rules = r#"
+        [[cleanup]]
+        rule = "stripSuffix"
+        fields = []
+        suffixes = ["(Original Mix)"]
+        "#;
+    let settings = ServerSettings::from_toml(&format!("{}\n{}", common::TEST_SETTINGS, rules)).unwrap();
+    assert_eq!(settings.validate(), vec![String::from("cleanup[0]: fields are empty")]);
+}
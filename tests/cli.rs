@@ -0,0 +1,59 @@
+//! Command line and environment parsing
+use std::collections::HashMap;
+use traktor_obs_relay::cli::{Args, ArgsError};
+
+fn args(list: &[&str]) -> Vec<String> {
+    list.iter().map(|arg| arg.to_string()).collect()
+}
+
+fn parse(list: &[&str], env: &[(&str, &str)]) -> Result<Args, ArgsError> {
+    let env: HashMap<String, String> = env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    Args::parse(args(list), |name| env.get(name).cloned())
+}
+
+#[test]
+fn options_take_values_inline_or_next() {
+    let parsed = parse(&["--port=9000", "--ws-port", "9001", "--replay=s.jsonl", "--speed", "2.5", "--check-config"], &[]).unwrap();
+    assert_eq!(parsed.port, Some(9000));
+    assert_eq!(parsed.ws_port, Some(9001));
+    assert_eq!(parsed.speed, Some(2.5));
+    assert!(parsed.check_config);
+    assert!(!parsed.print_config);
+}
+
+#[test]
+fn command_line_wins_over_environment() {
+    let parsed = parse(&["--port", "9000"], &[("PORT", "7000"), ("WS_PORT", "7001"), ("LOG_LEVEL", "Debug")]).unwrap();
+    assert_eq!(parsed.port, Some(9000));
+    assert_eq!(parsed.ws_port, Some(7001));
+    assert_eq!(parsed.log_level.as_deref(), Some("Debug"));
+}
+
+#[test]
+fn environment_only_sets_server_settings() {
+    let parsed = parse(&[], &[
+        ("RECORD", "session.jsonl"),
+        ("REPLAY", "session.jsonl"),
+        ("SIMULATE", "playlist.json"),
+        ("SPEED", "2.0"),
+        ("CONFIG", "other.toml"),
+    ]).unwrap();
+    assert_eq!(parsed.record, None);
+    assert_eq!(parsed.replay, None);
+    assert_eq!(parsed.simulate, None);
+    assert_eq!(parsed.speed, None);
+    assert_eq!(parsed.config.as_deref(), Some("other.toml"));
+}
+
+#[test]
+fn bad_arguments_are_refused() {
+    assert!(matches!(parse(&["--nope", "1"], &[]), Err(ArgsError::Invalid(_))));
+    assert!(matches!(parse(&["--port", "http"], &[]), Err(ArgsError::Invalid(_))));
+    assert!(matches!(parse(&["--replay", "s.jsonl", "--speed", "0"], &[]), Err(ArgsError::Invalid(_))));
+    assert!(matches!(parse(&["--speed", "2"], &[]), Err(ArgsError::Invalid(_))));
+    assert!(matches!(parse(&["--bind"], &[]), Err(ArgsError::Invalid(_))));
+    assert!(matches!(parse(&["stray"], &[]), Err(ArgsError::Invalid(_))));
+    assert!(matches!(parse(&[], &[("PORT", "http")]), Err(ArgsError::Invalid(_))));
+    assert!(matches!(parse(&["--port", "1", "-h"], &[]), Err(ArgsError::Help)));
+    assert!(matches!(parse(&["-V"], &[]), Err(ArgsError::Version)));
+}
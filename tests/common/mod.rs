@@ -0,0 +1,254 @@
+//! Shared harness for the integration tests: a relay bound to ephemeral ports and minimal clients for it
+#![allow(dead_code)]
+
+use serde_json::Value;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_tungstenite::tungstenite::{self, protocol::CloseFrame, Message, WebSocket};
+use traktor_obs_relay::{
+    app::{App, Endpoints},
+    settings::{ServerSettings, SettingsSource},
+};
+
+/// Settings of the relays under test, ports are left for the OS to pick
+pub const TEST_SETTINGS: &str = r#"
+[http]
+bind = "127.0.0.1"
+port = 0
+ws_port = 0
+webroot = "./assets"
+more_events = true
+
+[mixing]
+deck_list = [ "A", "B", "C", "D" ]
+deck_channel_map = { A = 1, B = 2, C = 3, D = 4 }
+overrides_file = "./target/no-overrides.json"
+stats_file = ""
+default_cover = "./assets/default.jpg"
+"#;
+
+/// Test settings with each `(section, key, value)` set, the value being written in TOML
+pub fn settings_with(values: &[(&str, &str, &str)]) -> String {
+    let mut settings: toml::Value = TEST_SETTINGS.parse().expect("test settings are TOML");
+    for (section, key, value) in values {
+        let value: toml::Value = format!("value = {}", value).parse().expect("setting value is TOML");
+        settings[*section]
+            .as_table_mut()
+            .expect("section exists in the test settings")
+            .insert(key.to_string(), value["value"].clone());
+    }
+    toml::to_string(&settings).expect("test settings can be written")
+}
+
+/// A file path in the temporary folder that is unique to the test
+pub fn temp_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("traktor-relay-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path.to_string_lossy().into_owned()
+}
+
+/// A started relay
+pub struct Relay {
+    pub app: Arc<App>,
+    pub endpoints: Endpoints,
+    pub runtime: tokio::runtime::Runtime,
+}
+
+/// A plain HTTP response
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+impl HttpResponse {
+    /// Body parsed as JSON
+    pub fn json(&self) -> Value {
+        serde_json::from_str(&self.body).unwrap_or_else(|e| panic!("body is not JSON ({}): {}", e, self.body))
+    }
+}
+
+impl Relay {
+    /// Start a relay with the test settings
+    pub fn start() -> Self {
+        Self::start_with(TEST_SETTINGS)
+    }
+
+    /// Start a relay with the given settings file contents
+    pub fn start_with(settings: &str) -> Self {
+        let settings = ServerSettings::from_toml(settings).expect("test settings are valid");
+        let problems = settings.validate();
+        assert!(problems.is_empty(), "test settings have problems: {:?}", problems);
+        Self::launch(settings, SettingsSource::default())
+    }
+
+    /// Start a relay reading its settings from a file, so that they can be reloaded
+    pub fn start_from_file(path: &str) -> Self {
+        let source = SettingsSource { path: path.to_string(), overrides: vec![] };
+        let settings = ServerSettings::read_validated(&source).expect("test settings are valid");
+        Self::launch(settings, source)
+    }
+
+    fn launch(settings: ServerSettings, source: SettingsSource) -> Self {
+        let app = App::new(settings, source);
+        let runtime = tokio::runtime::Runtime::new().expect("runtime starts");
+        let endpoints = {
+            let _runtime = runtime.enter();
+            app.start().expect("relay starts")
+        };
+        Self { app, endpoints, runtime }
+    }
+
+    /// Begin a graceful shutdown in the background
+    pub fn begin_shutdown(&self) -> tokio::task::JoinHandle<()> {
+        let app = self.app.clone();
+        self.runtime.spawn(async move { app.shutdown().await })
+    }
+
+    /// Make an HTTP request, `body` is sent as JSON
+    pub fn request(&self, method: &str, path: &str, body: Option<&str>) -> HttpResponse {
+        let mut stream = TcpStream::connect(self.endpoints.http).expect("HTTP server accepts connections");
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let body = body.unwrap_or("");
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            method, path, body.len(), body
+        )
+        .unwrap();
+
+        let mut raw = vec![];
+        stream.read_to_end(&mut raw).expect("HTTP response is read");
+        let raw = String::from_utf8_lossy(&raw).into_owned();
+        let (head, body) = raw.split_once("\r\n\r\n").expect("HTTP response has a header");
+        let status = head
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .expect("HTTP response has a status code");
+        HttpResponse { status, body: body.to_string() }
+    }
+
+    pub fn get(&self, path: &str) -> HttpResponse {
+        self.request("GET", path, None)
+    }
+
+    pub fn post(&self, path: &str, body: &str) -> HttpResponse {
+        self.request("POST", path, Some(body))
+    }
+
+    /// Post a Traktor call and check that it was accepted
+    pub fn traktor(&self, path: &str, body: Value) {
+        let resp = self.post(path, &body.to_string());
+        assert_eq!(resp.status, 204, "{} was not accepted: {}", path, resp.body);
+    }
+
+    /// Connect a websocket client and wait until the relay has registered it
+    pub fn subscribe(&self) -> WsClient {
+        let peers = || self.get("/status").json()["wsPeers"].as_u64().unwrap();
+        let before = peers();
+        let stream = TcpStream::connect(self.endpoints.ws).expect("websocket server accepts connections");
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let url = format!("ws://{}/", self.endpoints.ws);
+        let (socket, _) = tungstenite::client(url.as_str(), stream).expect("websocket handshake");
+        wait_until(|| peers() > before);
+        WsClient { socket }
+    }
+}
+
+/// A websocket client of the relay
+pub struct WsClient {
+    socket: WebSocket<TcpStream>,
+}
+
+impl WsClient {
+    /// Send a JSON message
+    pub fn send(&mut self, msg: Value) {
+        self.socket.write_message(Message::Text(msg.to_string())).expect("websocket message is sent");
+    }
+
+    /// Send a command and wait for the response to it
+    pub fn command(&mut self, command: Value) -> Value {
+        let id = command["id"].clone();
+        self.send(command);
+        self.next_matching(|msg| msg["event"] == "response" && msg["id"] == id)
+    }
+
+    /// Receive the next frame of any kind, answering pings on the way
+    pub fn next_frame(&mut self) -> Message {
+        self.socket.read_message().expect("websocket frame is received")
+    }
+
+    /// Receive the next JSON message
+    pub fn next(&mut self) -> Value {
+        loop {
+            match self.socket.read_message().expect("websocket message is received") {
+                Message::Text(text) => return serde_json::from_str(&text).expect("websocket message is JSON"),
+                Message::Close(frame) => panic!("websocket closed: {:?}", frame),
+                _ => continue,
+            }
+        }
+    }
+
+    /// Receive messages until the relay closes the connection, returning the close frame
+    pub fn closed(&mut self) -> Option<CloseFrame<'static>> {
+        loop {
+            match self.socket.read_message().expect("websocket message is received") {
+                Message::Close(frame) => {
+                    // Acknowledge the close like a browser would
+                    let _ = self.socket.write_pending();
+                    return frame;
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    /// Receive messages until one satisfies `pred`
+    pub fn next_matching<F: Fn(&Value) -> bool>(&mut self, pred: F) -> Value {
+        loop {
+            let msg = self.next();
+            if pred(&msg) {
+                return msg;
+            }
+        }
+    }
+}
+
+/// Poll `cond` until it holds, failing the test after a few seconds
+pub fn wait_until<F: Fn() -> bool>(cond: F) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !cond() {
+        assert!(Instant::now() < deadline, "condition was not met in time");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// A `deckLoaded` payload as sent by Traktor API Client
+pub fn track(title: &str, artist: &str) -> Value {
+    serde_json::json!({
+        "filePath": format!("/music/{} - {}.mp3", artist, title),
+        "title": title,
+        "artist": artist,
+        "album": "",
+        "genre": "Techno",
+        "comment": "",
+        "comment2": "",
+        "label": "",
+        "mix": "",
+        "remixer": "",
+        "key": "1m",
+        "keyText": "Am",
+        "gridOffset": 0.1,
+        "trackLength": 300.0,
+        "elapsedTime": 0.0,
+        "nextCuePos": null,
+        "bpm": 128.0,
+        "tempo": 1.0,
+        "resultingKey": "1m",
+        "isPlaying": false,
+        "isSynced": true,
+        "isKeyLockOn": true
+    })
+}
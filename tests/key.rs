@@ -0,0 +1,51 @@
+//! Key notations
+use traktor_obs_relay::api::key::{KeyRelation, MusicalKey};
+
+#[test]
+fn notations_name_the_same_key() {
+    let a_minor = MusicalKey { tonic: 9, minor: true };
+    for text in IntoIterator::into_iter(["1m", "8A", "Am", "A minor", "a min"]) {
+        assert_eq!(MusicalKey::parse(text), Some(a_minor), "{}", text);
+    }
+    let notation = a_minor.notation();
+    assert_eq!(notation.camelot, "8A");
+    assert_eq!(notation.open_key, "1m");
+    assert_eq!(notation.musical, "Am");
+    assert_eq!(notation.name, "A minor");
+}
+
+#[test]
+fn wheels_go_round_in_fifths() {
+    let cases = [("1d", "8B", "C"), ("2d", "9B", "G"), ("6d", "1B", "B"), ("12d", "7B", "F"), ("4m", "11A", "F#m"), ("9m", "4A", "Fm")];
+    for (open_key, camelot, musical) in IntoIterator::into_iter(cases) {
+        let key = MusicalKey::parse(open_key).unwrap().notation();
+        assert_eq!((key.camelot.as_str(), key.musical.as_str()), (camelot, musical), "{}", open_key);
+        assert_eq!(MusicalKey::parse(camelot).unwrap().notation().open_key, open_key);
+        assert_eq!(MusicalKey::parse(musical).unwrap().notation().open_key, open_key);
+    }
+}
+
+#[test]
+fn enharmonic_spellings_are_the_same_key() {
+    assert_eq!(MusicalKey::parse("D#m"), MusicalKey::parse("Ebm"));
+    assert_eq!(MusicalKey::parse("Gb"), MusicalKey::parse("F# major"));
+    assert_eq!(MusicalKey::parse("Cb"), MusicalKey::parse("B"));
+}
+
+#[test]
+fn garbage_is_not_a_key() {
+    for text in IntoIterator::into_iter(["", "13m", "0A", "H", "Am7", "8X", "m"]) {
+        assert_eq!(MusicalKey::parse(text), None, "{}", text);
+    }
+}
+
+#[test]
+fn relations_follow_the_camelot_wheel() {
+    let key = |text| MusicalKey::parse(text).unwrap();
+    assert_eq!(key("8A").relation(&key("Am")), KeyRelation::Same);
+    assert_eq!(key("8A").relation(&key("8B")), KeyRelation::Relative);
+    assert_eq!(key("8A").relation(&key("9A")), KeyRelation::Adjacent);
+    assert_eq!(key("12B").relation(&key("1B")), KeyRelation::Adjacent);
+    assert_eq!(key("8A").relation(&key("9B")), KeyRelation::Clash);
+    assert_eq!(key("8A").relation(&key("3A")), KeyRelation::Clash);
+}
@@ -0,0 +1,47 @@
+//! Prometheus metrics of a relay
+mod common;
+
+use common::{track, Relay};
+use serde_json::json;
+
+/// Value of the metric `name` in the metrics text
+fn value(metrics: &str, name: &str) -> f64 {
+    let line = metrics.lines().find(|line| line.starts_with(&format!("{} ", name)));
+    line.unwrap_or_else(|| panic!("{} is in the metrics:\n{}", name, metrics))[name.len() + 1..].parse().unwrap()
+}
+
+#[test]
+fn metrics_count_traktor_calls_and_requests() {
+    let relay = Relay::start();
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    relay.traktor("/updateDeck/A", json!({ "isPlaying": true }));
+    relay.traktor("/updateDeck/A", json!({ "elapsedTime": 1.0 }));
+    relay.traktor("/updateMasterClock", json!({ "deck": "A", "bpm": 124.0 }));
+    assert_eq!(relay.get("/nowPlaying").status, 200);
+
+    let resp = relay.get("/metrics");
+    assert_eq!(resp.status, 200);
+    let value = |name: &str| value(&resp.body, name);
+    assert_eq!(value("traktor_relay_ingest_total{route=\"deckLoaded\"}"), 1.0);
+    assert_eq!(value("traktor_relay_ingest_total{route=\"updateDeck\"}"), 2.0);
+    assert_eq!(value("traktor_relay_ingest_total{route=\"updateMasterClock\"}"), 1.0);
+    assert_eq!(value("traktor_relay_ingest_total{route=\"updateChannel\"}"), 0.0);
+    assert_eq!(value("traktor_relay_master_bpm"), 124.0);
+    assert_eq!(value("traktor_relay_decks_on_air"), 1.0);
+    assert_eq!(value("traktor_relay_ws_peers"), 0.0);
+    assert_eq!(value("traktor_relay_http_request_duration_seconds_count{route=\"nowPlaying\"}"), 1.0);
+    assert_eq!(value("traktor_relay_http_request_duration_seconds_bucket{route=\"updateDeck\",le=\"+Inf\"}"), 2.0);
+}
+
+#[test]
+fn ws_bytes_are_counted_per_message_sent() {
+    let relay = Relay::start();
+    let mut ws = relay.subscribe();
+    let reply = ws.command(json!({ "id": 1, "command": "subscribe", "events": ["configChanged"] }));
+
+    // Broadcasts the client is not subscribed to are not sent to it
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    let metrics = relay.get("/metrics").body;
+    assert!(value(&metrics, "traktor_relay_ws_broadcasts_total") >= 1.0);
+    assert_eq!(value(&metrics, "traktor_relay_ws_sent_bytes_total"), reply.to_string().len() as f64);
+}
@@ -0,0 +1,243 @@
+//! OBS WebSocket client against a mock OBS
+mod common;
+
+use common::{track, Relay};
+use serde_json::{json, Value};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::{self, protocol::frame::coding::CloseCode, protocol::CloseFrame, Message};
+use traktor_obs_relay::obs;
+
+const PASSWORD: &str = "hunter2";
+
+/// A mock OBS WebSocket server asking for `PASSWORD`, passing the requests it gets to the test
+struct MockObs {
+    url: String,
+    requests: mpsc::Receiver<Value>,
+    /// Whether the relay has identified itself
+    identified: Arc<AtomicBool>,
+}
+
+impl MockObs {
+    /// Start the mock, refusing the requests of the `failing` type
+    fn start(failing: &'static str) -> Self {
+        Self::start_on(TcpListener::bind("127.0.0.1:0").unwrap(), failing)
+    }
+
+    /// Start the mock on the given listener, refusing the requests of the `failing` type
+    fn start_on(listener: TcpListener, failing: &'static str) -> Self {
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (tx, requests) = mpsc::channel();
+        let identified = Arc::new(AtomicBool::new(false));
+        let identified_mock = identified.clone();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut ws = tungstenite::accept(stream).unwrap();
+            let send = |ws: &mut tungstenite::WebSocket<_>, op: u64, d: Value| {
+                ws.write_message(Message::Text(json!({ "op": op, "d": d }).to_string())).unwrap();
+            };
+            send(&mut ws, 0, json!({
+                "obsWebSocketVersion": "5.0.0",
+                "rpcVersion": 1,
+                "authentication": { "challenge": "challenge", "salt": "salt" },
+            }));
+            loop {
+                let msg: Value = match ws.read_message() {
+                    Ok(Message::Text(text)) => serde_json::from_str(&text).unwrap(),
+                    Ok(_) => continue,
+                    Err(_) => return,
+                };
+                let d = &msg["d"];
+                match msg["op"].as_u64() {
+                    Some(1) => {
+                        if d["authentication"] != json!(obs::authentication(PASSWORD, "salt", "challenge")) {
+                            let _ = ws.close(Some(CloseFrame { code: CloseCode::from(4009), reason: "Authentication failed.".into() }));
+                            let _ = ws.write_pending();
+                            return;
+                        }
+                        send(&mut ws, 2, json!({ "negotiatedRpcVersion": 1 }));
+                        identified_mock.store(true, Ordering::SeqCst);
+                    }
+                    Some(6) => {
+                        let ok = d["requestType"] != failing;
+                        let response_data = match d["requestType"].as_str() {
+                            Some("GetSceneItemId") => json!({ "sceneItemId": 7 }),
+                            _ => json!(null),
+                        };
+                        send(&mut ws, 7, json!({
+                            "requestType": d["requestType"],
+                            "requestId": d["requestId"],
+                            "requestStatus": { "result": ok, "code": if ok { 100 } else { 600 } },
+                            "responseData": response_data,
+                        }));
+                        let _ = tx.send(json!({ "type": d["requestType"], "data": d["requestData"] }));
+                    }
+                    _ => {}
+                }
+            }
+        });
+        Self { url, requests, identified }
+    }
+
+    /// Settings connecting to the mock with the given password and rules
+    fn settings(&self, password: &str, rules: &str) -> String {
+        settings(&self.url, password, rules)
+    }
+
+    fn next(&self) -> Value {
+        self.requests.recv_timeout(Duration::from_secs(5)).expect("OBS gets a request")
+    }
+}
+
+/// Settings connecting to OBS at `url` with the given password and rules
+fn settings(url: &str, password: &str, rules: &str) -> String {
+    format!(
+        "{}\n[obs]\nurl = {:?}\npassword = {:?}\nreconnect_interval = 1\n{}\n",
+        common::TEST_SETTINGS, url, password, rules
+    )
+}
+
+const FIRST_TRACK_SCENE: &str = r#"
+    [[obs.rules]]
+    on = "firstTrack"
+    action = "switchScene"
+    scene = "Live"
+    "#;
+
+fn wait_connected(relay: &Relay) {
+    common::wait_until(|| relay.get("/status").json()["obsConnected"] == true);
+}
+
+#[test]
+fn mix_events_drive_obs() {
+    let mock = MockObs::start("");
+    let relay = Relay::start_with(&mock.settings(PASSWORD, r#"
+        [[obs.rules]]
+        on = "firstTrack"
+        action = "switchScene"
+        scene = "Live"
+
+        [[obs.rules]]
+        on = "trackChanged"
+        action = "setText"
+        source = "Now Playing"
+        text = "%artist% — %title%"
+
+        [[obs.rules]]
+        on = "trackChanged"
+        action = "chapterMarker"
+
+        [[obs.rules]]
+        on = "soloEnded"
+        action = "setSourceVisible"
+        scene = "Live"
+        source = "Mixing"
+        "#));
+    wait_connected(&relay);
+
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    relay.traktor("/updateDeck/A", json!({ "isPlaying": true }));
+    assert_eq!(mock.next(), json!({ "type": "SetCurrentProgramScene", "data": { "sceneName": "Live" } }));
+    assert_eq!(mock.next(), json!({
+        "type": "SetInputSettings",
+        "data": { "inputName": "Now Playing", "inputSettings": { "text": "Someone — Rise" } },
+    }));
+    assert_eq!(mock.next(), json!({ "type": "CreateRecordChapter", "data": { "chapterName": "Someone — Rise" } }));
+
+    relay.traktor("/deckLoaded/B", track("Fall", "Someone Else"));
+    relay.traktor("/updateDeck/B", json!({ "isPlaying": true }));
+    assert_eq!(mock.next()["data"]["inputSettings"]["text"], "Someone Else — Fall");
+    assert_eq!(mock.next()["type"], "CreateRecordChapter");
+    assert_eq!(mock.next(), json!({ "type": "GetSceneItemId", "data": { "sceneName": "Live", "sourceName": "Mixing" } }));
+    assert_eq!(mock.next(), json!({
+        "type": "SetSceneItemEnabled",
+        "data": { "sceneName": "Live", "sceneItemId": 7, "sceneItemEnabled": true },
+    }));
+
+    // The track left playing takes over, but the scene is only switched on the first track
+    relay.traktor("/updateDeck/B", json!({ "isPlaying": false }));
+    assert_eq!(mock.next()["data"]["inputSettings"]["text"], "Someone — Rise");
+    assert_eq!(mock.next()["type"], "CreateRecordChapter");
+
+    // Pausing ends the solo, but resuming the same track is no track change
+    relay.traktor("/updateDeck/A", json!({ "isPlaying": false }));
+    assert_eq!(mock.next()["type"], "GetSceneItemId");
+    assert_eq!(mock.next()["type"], "SetSceneItemEnabled");
+    relay.traktor("/updateDeck/A", json!({ "isPlaying": true }));
+    assert!(mock.requests.recv_timeout(Duration::from_millis(200)).is_err());
+}
+
+#[test]
+fn refused_requests_do_not_stop_the_next_ones() {
+    let mock = MockObs::start("SetCurrentProgramScene");
+    let relay = Relay::start_with(&mock.settings(PASSWORD, r#"
+        [[obs.rules]]
+        on = "soloStarted"
+        action = "switchScene"
+        scene = "Nowhere"
+
+        [[obs.rules]]
+        on = "soloStarted"
+        action = "chapterMarker"
+        name = "{title}"
+        "#));
+    wait_connected(&relay);
+
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    relay.traktor("/updateDeck/A", json!({ "isPlaying": true }));
+    assert_eq!(mock.next()["type"], "SetCurrentProgramScene");
+    assert_eq!(mock.next(), json!({ "type": "CreateRecordChapter", "data": { "chapterName": "Rise" } }));
+    assert_eq!(relay.get("/status").json()["obsConnected"], true);
+}
+
+#[test]
+fn wrong_password_is_not_connected() {
+    let mock = MockObs::start("");
+    let relay = Relay::start_with(&mock.settings("wrong", r#"
+        [[obs.rules]]
+        on = "firstTrack"
+        action = "switchScene"
+        scene = "Live"
+        "#));
+    std::thread::sleep(Duration::from_millis(200));
+    assert_eq!(relay.get("/status").json()["obsConnected"], false);
+
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    relay.traktor("/updateDeck/A", json!({ "isPlaying": true }));
+    assert!(mock.requests.recv_timeout(Duration::from_millis(200)).is_err());
+}
+
+#[test]
+fn first_track_is_caught_up_with_when_obs_connects_late() {
+    let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let relay = Relay::start_with(&settings(&format!("ws://{}", addr), PASSWORD, FIRST_TRACK_SCENE));
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    relay.traktor("/updateDeck/A", json!({ "isPlaying": true }));
+    assert_eq!(relay.get("/status").json()["obsConnected"], false);
+
+    let mock = MockObs::start_on(TcpListener::bind(addr).unwrap(), "");
+    assert_eq!(mock.next(), json!({ "type": "SetCurrentProgramScene", "data": { "sceneName": "Live" } }));
+    assert!(mock.requests.recv_timeout(Duration::from_millis(200)).is_err());
+}
+
+#[test]
+fn reloaded_url_is_connected_to() {
+    let first = MockObs::start("");
+    let second = MockObs::start("");
+    let path = common::temp_path("obs-reload.toml");
+    std::fs::write(&path, first.settings(PASSWORD, FIRST_TRACK_SCENE)).unwrap();
+    let relay = Relay::start_from_file(&path);
+    wait_connected(&relay);
+
+    std::fs::write(&path, second.settings(PASSWORD, FIRST_TRACK_SCENE)).unwrap();
+    assert_eq!(relay.post("/admin/reload", "").status, 204);
+    common::wait_until(|| second.identified.load(Ordering::SeqCst));
+
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    relay.traktor("/updateDeck/A", json!({ "isPlaying": true }));
+    assert_eq!(second.next()["type"], "SetCurrentProgramScene");
+    assert!(first.requests.recv_timeout(Duration::from_millis(200)).is_err());
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,65 @@
+//! Recording Traktor sessions and replaying them
+mod common;
+
+use common::{track, Relay};
+use serde_json::json;
+use traktor_obs_relay::recorder::{self, RecordedCall};
+
+#[test]
+fn recorded_session_replays_into_another_relay() {
+    let path = common::temp_path("session.jsonl");
+    let recording = Relay::start();
+    recording.app.start_recording(&path).unwrap();
+    recording.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    recording.traktor("/updateDeck/A", json!({ "isPlaying": true }));
+    recording.traktor("/updateMasterClock", json!({ "deck": "A", "bpm": 124.0 }));
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let calls: Vec<RecordedCall> = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    let routes: Vec<_> = calls.iter().map(|call| (call.route.as_str(), call.id.as_deref())).collect();
+    assert_eq!(routes, [("deckLoaded", Some("A")), ("updateDeck", Some("A")), ("updateMasterClock", None)]);
+    assert!(calls.windows(2).all(|pair| pair[0].at <= pair[1].at));
+
+    // Lines that cannot be replayed are skipped
+    let replay_path = common::temp_path("replay.jsonl");
+    std::fs::write(&replay_path, format!("not json\n{{\"at\":0,\"route\":\"nowhere\",\"body\":{{}}}}\n\n{}", contents)).unwrap();
+    let replaying = Relay::start();
+    {
+        let _runtime = replaying.runtime.enter();
+        replaying.app.spawn(recorder::spawn_replay(&replaying.app, &replay_path, 100.0).unwrap());
+    }
+    common::wait_until(|| replaying.get("/nowPlaying").json()["bpm"] == 124.0);
+    let np = replaying.get("/nowPlaying").json();
+    assert_eq!(np["songsOnAir"][0]["title"], "Rise");
+    assert_eq!(np["songsOnAir"][0]["isPlaying"], true);
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&replay_path);
+}
+
+#[test]
+fn replay_stops_on_shutdown() {
+    let path = common::temp_path("slow-session.jsonl");
+    let calls = [
+        json!({ "at": 0.0, "route": "deckLoaded", "id": "A", "body": track("Rise", "Someone") }),
+        json!({ "at": 60.0, "route": "updateDeck", "id": "A", "body": { "isPlaying": true } }),
+    ];
+    std::fs::write(&path, calls.iter().map(|call| format!("{}\n", call)).collect::<String>()).unwrap();
+    let relay = Relay::start();
+    {
+        let _runtime = relay.runtime.enter();
+        relay.app.spawn(recorder::spawn_replay(&relay.app, &path, 1.0).unwrap());
+    }
+    common::wait_until(|| relay.get("/nowPlaying").json()["songsOnAir"][0]["title"] == "Rise");
+
+    let started = std::time::Instant::now();
+    relay.runtime.block_on(relay.begin_shutdown()).unwrap();
+    assert!(started.elapsed() < std::time::Duration::from_secs(1), "{:?}", started.elapsed());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn missing_session_is_an_error() {
+    let path = common::temp_path("no-session.jsonl");
+    let relay = Relay::start();
+    assert!(recorder::spawn_replay(&relay.app, &path, 1.0).is_err());
+}
@@ -0,0 +1,90 @@
+//! Consistency checks of the settings
+mod common;
+
+use traktor_obs_relay::settings::ServerSettings;
+
+fn problems(values: &[(&str, &str, &str)]) -> Vec<String> {
+    ServerSettings::from_toml(&common::settings_with(values)).expect("settings parse").validate()
+}
+
+#[test]
+fn test_settings_are_consistent() {
+    assert_eq!(problems(&[]), Vec::<String>::new());
+}
+
+#[test]
+fn every_problem_is_listed() {
+    let found = problems(&[
+        ("http", "port", "9000"),
+        ("http", "ws_port", "9000"),
+        ("http", "webroot", r#""./Cargo.toml""#),
+        ("mixing", "deck_list", r#"["A", "A", "B"]"#),
+        ("mixing", "deck_channel_map", "{ A = 1, C = 3 }"),
+    ]);
+    assert_eq!(found, [
+        "http.port and http.ws_port are both set to 9000",
+        "http.webroot: \"./Cargo.toml\" is not a folder",
+        "mixing.deck_list: deck A is listed more than once",
+        "mixing.deck_channel_map: deck B has no channel assigned",
+        "mixing.deck_channel_map: deck C is not in mixing.deck_list",
+    ]);
+}
+
+#[test]
+fn out_of_range_values_are_problems() {
+    let found = problems(&[
+        ("http", "ws_ping_interval", "0"),
+        ("http", "ws_queue_size", "0"),
+        ("http", "bpm_precision", "7"),
+        ("http", "bpm_min_delta", "-0.1"),
+        ("mixing", "traktor_timeout", "0"),
+    ]);
+    assert_eq!(found, [
+        "http.ws_ping_interval must be at least 1 second",
+        "http.ws_queue_size must be at least 1",
+        "http.bpm_precision (7) must be at most 6 decimals",
+        "http.bpm_min_delta (-0.1) must not be negative",
+        "mixing.traktor_timeout must be at least 1 second",
+    ]);
+}
+
+#[test]
+fn unknown_log_level_is_a_problem() {
+    let settings = format!("log_level = \"Loud\"\n{}", common::TEST_SETTINGS);
+    let found = ServerSettings::from_toml(&settings).unwrap().validate();
+    assert_eq!(found, ["log_level: \"Loud\" is not a log level (Error, Warn, Info, Debug, Trace)"]);
+}
+
+#[test]
+fn stream_urls_are_http_or_https() {
+    let settings = format!(
+        "{}\n[[outputs.streams]]\nserver = \"shoutcast1\"\nurl = \"https://radio.example\"\n\n[[outputs.streams]]\nserver = \"shoutcast1\"\nurl = \"ftp://radio.example\"\n",
+        common::TEST_SETTINGS
+    );
+    let found = ServerSettings::from_toml(&settings).unwrap().validate();
+    assert_eq!(found, ["outputs.streams[1]: url (ftp://radio.example) must start with http:// or https://"]);
+}
+
+#[test]
+fn obs_urls_are_ws_or_wss() {
+    let validated = |url: &str| {
+        let settings = format!("{}\n[obs]\nurl = {:?}\n", common::TEST_SETTINGS, url);
+        ServerSettings::from_toml(&settings).unwrap().validate()
+    };
+    assert!(validated("ws://127.0.0.1:4455").is_empty());
+    assert!(validated("wss://obs.example").is_empty());
+    assert_eq!(validated("http://obs.example"), ["obs.url (http://obs.example) must start with ws:// or wss://"]);
+}
+
+#[test]
+fn printed_settings_mask_the_passwords() {
+    let settings = format!(
+        "{}\n[obs]\nurl = \"ws://127.0.0.1:4455\"\npassword = \"obs-secret\"\n\n[[outputs.streams]]\nserver = \"icecast\"\nurl = \"http://radio.example\"\nmount = \"/live\"\npassword = \"hackme\"\n\n[[outputs.streams]]\nserver = \"shoutcast1\"\nurl = \"http://radio.example\"\n",
+        common::TEST_SETTINGS
+    );
+    let printed = ServerSettings::from_toml(&settings).unwrap().to_toml().unwrap();
+    assert!(!printed.contains("obs-secret") && !printed.contains("hackme"), "{}", printed);
+    assert_eq!(printed.matches("password = '***'").count(), 2, "{}", printed);
+    // Passwords that are not set stay empty
+    assert_eq!(printed.matches("password = ''").count(), 1, "{}", printed);
+}
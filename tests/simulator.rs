@@ -0,0 +1,80 @@
+//! Simulated Traktor mixing a playlist
+mod common;
+
+use common::{track, Relay};
+use serde_json::{json, Value};
+use traktor_obs_relay::simulator;
+
+fn playlist(path: &str, tracks: Vec<Value>) {
+    let playlist = json!({ "soloTime": 0.5, "mixTime": 0.3, "tickInterval": 0.05, "tracks": tracks });
+    std::fs::write(path, playlist.to_string()).unwrap();
+}
+
+/// Titles and decks of the songs on air
+fn on_air(relay: &Relay) -> Vec<(String, String)> {
+    let np = relay.get("/nowPlaying").json();
+    np["songsOnAir"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|song| (song["title"].as_str().unwrap().to_string(), song["deck"].as_str().unwrap().to_string()))
+        .collect()
+}
+
+#[test]
+fn playlist_is_mixed_across_two_decks() {
+    let path = common::temp_path("playlist.json");
+    let mut second = track("Fall", "Someone Else");
+    second["bpm"] = json!(130.0);
+    playlist(&path, vec![track("Rise", "Someone"), second]);
+
+    let relay = Relay::start();
+    let simulation = {
+        let _runtime = relay.runtime.enter();
+        simulator::spawn_simulation(&relay.app, &path).unwrap()
+    };
+    relay.app.spawn(simulation);
+
+    common::wait_until(|| on_air(&relay) == [(String::from("Rise"), String::from("A"))]);
+    assert_eq!(relay.get("/nowPlaying").json()["bpm"], 128.0);
+
+    // The next track is synced in, then takes over with its own tempo
+    common::wait_until(|| on_air(&relay).len() == 2);
+    common::wait_until(|| on_air(&relay) == [(String::from("Fall"), String::from("B"))]);
+    assert_eq!(relay.get("/nowPlaying").json()["bpm"], 130.0);
+
+    // The playlist starts over
+    common::wait_until(|| on_air(&relay).contains(&(String::from("Rise"), String::from("A"))));
+    let _ = std::fs::remove_file(&path);
+
+    // The simulation stops with the relay
+    let started = std::time::Instant::now();
+    relay.runtime.block_on(relay.begin_shutdown()).unwrap();
+    assert!(started.elapsed() < std::time::Duration::from_secs(1), "{:?}", started.elapsed());
+}
+
+#[test]
+fn unusable_playlists_are_refused() {
+    let path = common::temp_path("bad-playlist.json");
+    let relay = Relay::start();
+    let refused = |schedule: Value| {
+        let mut playlist = schedule;
+        playlist["tracks"] = json!([track("Rise", "Someone")]);
+        std::fs::write(&path, playlist.to_string()).unwrap();
+        simulator::spawn_simulation(&relay.app, &path).err().map(|e| e.to_string()).unwrap_or_default()
+    };
+
+    playlist(&path, vec![]);
+    assert!(simulator::spawn_simulation(&relay.app, &path).is_err());
+
+    assert!(refused(json!({ "tickInterval": 0 })).contains("tickInterval"));
+    assert!(refused(json!({ "soloTime": -1 })).contains("soloTime"));
+    // Nothing to wait for between the calls
+    assert!(refused(json!({ "soloTime": 0, "mixTime": 0 })).contains("cannot both be 0"));
+    // Too large for the schedule, read as infinite
+    assert!(refused(json!({ "tickInterval": 1e39 })).contains("tickInterval"));
+    assert!(refused(json!({ "mixTime": 1e39 })).contains("mixTime"));
+
+    let _ = std::fs::remove_file(&path);
+    assert!(simulator::spawn_simulation(&relay.app, &path).is_err());
+}
@@ -0,0 +1,94 @@
+//! Now Playing decisions made on the relay state, without any servers
+mod common;
+
+use serde_json::json;
+use std::time::Duration;
+use traktor_obs_relay::{
+    api::deck::{DeckStatus, DeckStatusUpdate},
+    settings::{MixingSettings, ServerSettings},
+    state::RelayState,
+    status::Ingest,
+};
+
+fn mixing() -> MixingSettings {
+    ServerSettings::from_toml(common::TEST_SETTINGS).unwrap().mixing
+}
+
+fn deck(title: &str) -> DeckStatus {
+    serde_json::from_value(common::track(title, "Someone")).unwrap()
+}
+
+fn delta(value: serde_json::Value) -> DeckStatusUpdate {
+    serde_json::from_value(value).unwrap()
+}
+
+#[test]
+fn mapped_channels_start_on_air() {
+    let state = RelayState::new(&mixing());
+    for channel in 1..=4 {
+        assert!(state.channels[&channel].is_on_air);
+    }
+}
+
+#[test]
+fn songs_on_air_follow_deck_list_order() {
+    let mixing = mixing();
+    let mut state = RelayState::new(&mixing);
+    state.load_deck("C".into(), deck("Third"));
+    state.load_deck("A".into(), deck("First"));
+
+    let titles: Vec<String> = state.songs_on_air(&mixing).into_iter().map(|d| d.title).collect();
+    assert_eq!(titles, vec!["First", "Third"]);
+}
+
+#[test]
+fn loaded_deck_knows_its_letter() {
+    let mut state = RelayState::new(&mixing());
+    state.load_deck("B".into(), deck("Rise"));
+    assert_eq!(state.decks["B"].deck.as_deref(), Some("B"));
+}
+
+#[test]
+fn update_of_empty_deck_is_rejected() {
+    let mut state = RelayState::new(&mixing());
+    assert_eq!(state.update_deck("A", delta(json!({ "isPlaying": true })), true), None);
+}
+
+#[test]
+fn minor_updates_only_count_with_more_events() {
+    let mut state = RelayState::new(&mixing());
+    state.load_deck("A".into(), deck("Rise"));
+    assert_eq!(state.update_deck("A", delta(json!({ "elapsedTime": 1.0 })), false), Some(false));
+    assert_eq!(state.update_deck("A", delta(json!({ "elapsedTime": 2.0 })), true), Some(true));
+    assert_eq!(state.update_deck("A", delta(json!({ "isPlaying": true })), false), Some(true));
+    assert_eq!(state.decks["A"].elapsed_time, 2.0);
+}
+
+#[test]
+fn traktor_is_connected_after_a_call() {
+    let mut state = RelayState::default();
+    assert!(!state.traktor_connected(Duration::from_secs(30)));
+    state.record_ingest(Ingest::UpdateDeck);
+    assert!(state.traktor_connected(Duration::from_secs(30)));
+    assert!(state.since(Ingest::DeckLoaded).is_none());
+}
+
+#[test]
+fn id_marker_hides_track() {
+    let mixing = mixing();
+    let mut state = RelayState::new(&mixing);
+    let mut dubplate = deck("Dubplate");
+    dubplate.comment2 = String::from("unreleased [ID] do not post");
+    state.load_deck("A".into(), dubplate);
+    state.load_deck("B".into(), deck("Released"));
+
+    let songs = state.songs_on_air(&mixing);
+    assert!(songs[0].is_hidden);
+    assert_eq!((songs[0].artist.as_str(), songs[0].title.as_str()), ("ID", "ID"));
+    assert_eq!(songs[0].file_path, "");
+    assert_eq!(songs[0].comment2, "");
+    assert_eq!(songs[0].bpm, 128.0);
+    assert!(!songs[1].is_hidden);
+    assert!(state.is_hidden("A", &mixing));
+    assert!(!state.is_hidden("B", &mixing));
+}
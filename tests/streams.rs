@@ -0,0 +1,172 @@
+//! Song titles sent to streaming servers, against a local HTTP stand-in
+mod common;
+
+use common::{track, Relay};
+use serde_json::json;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// A request as the stand-in has received it
+#[derive(Debug)]
+struct Received {
+    target: String,
+    authorization: Option<String>,
+}
+
+/// A local HTTP server answering the requests with the given statuses in turn, then with 200
+struct StandIn {
+    url: String,
+    requests: mpsc::Receiver<Received>,
+}
+
+impl StandIn {
+    fn start(statuses: Vec<u16>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, requests) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut statuses = statuses.into_iter();
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut lines = vec![];
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 || line.trim().is_empty() {
+                        break;
+                    }
+                    lines.push(line.trim().to_string());
+                }
+                let target = lines.first().and_then(|l| l.split_whitespace().nth(1)).unwrap_or_default().to_string();
+                let authorization = lines
+                    .iter()
+                    .find_map(|l| l.strip_prefix("authorization: ").or_else(|| l.strip_prefix("Authorization: ")))
+                    .map(String::from);
+                let status = statuses.next().unwrap_or(200);
+                let body = "<?xml version=\"1.0\"?><iceresponse><return>1</return></iceresponse>";
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {} Whatever\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+                    status, body.len(), body
+                );
+                let _ = tx.send(Received { target, authorization });
+            }
+        });
+        Self { url, requests }
+    }
+
+    fn next(&self) -> Received {
+        self.requests.recv_timeout(Duration::from_secs(5)).expect("the stand-in gets a request")
+    }
+
+    fn nothing_more(&self) -> bool {
+        self.requests.recv_timeout(Duration::from_millis(200)).is_err()
+    }
+}
+
+fn settings(streams: &str) -> String {
+    format!("{}\n[outputs]\n{}\n", common::TEST_SETTINGS, streams)
+}
+
+#[test]
+fn icecast_gets_the_current_song() {
+    let icecast = StandIn::start(vec![]);
+    let relay = Relay::start_with(&settings(&format!(r#"
+        [[outputs.streams]]
+        server = "icecast"
+        url = "{}"
+        mount = "/live.mp3"
+        password = "hackme"
+        "#, icecast.url)));
+
+    relay.traktor("/deckLoaded/A", track("Rise & Shine", "Someone"));
+    let request = icecast.next();
+    assert_eq!(request.target, "/admin/metadata?mount=%2Flive.mp3&mode=updinfo&song=Someone%20-%20Rise%20%26%20Shine");
+    // source:hackme
+    assert_eq!(request.authorization.as_deref(), Some("Basic c291cmNlOmhhY2ttZQ=="));
+
+    // Ticks of the same track are not sent again
+    relay.traktor("/updateDeck/A", json!({ "isPlaying": true }));
+    relay.traktor("/updateDeck/A", json!({ "elapsedTime": 10.0 }));
+    assert!(icecast.nothing_more());
+
+    relay.traktor("/deckLoaded/B", track("Fall", "Someone Else"));
+    relay.traktor("/updateDeck/B", json!({ "isPlaying": true }));
+    assert!(icecast.next().target.ends_with("&song=Someone%20Else%20-%20Fall"));
+}
+
+#[test]
+fn shoutcast_gets_the_current_song() {
+    let v1 = StandIn::start(vec![]);
+    let v2 = StandIn::start(vec![]);
+    let relay = Relay::start_with(&settings(&format!(r#"
+        [[outputs.streams]]
+        server = "shoutcast1"
+        url = "{}"
+        password = "pass word"
+        song = "{{title}}"
+
+        [[outputs.streams]]
+        server = "shoutcast2"
+        url = "{}/"
+        sid = 2
+        password = "secret"
+        "#, v1.url, v2.url)));
+
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    let request = v1.next();
+    assert_eq!(request.target, "/admin.cgi?pass=pass%20word&mode=updinfo&song=Rise");
+    assert_eq!(request.authorization, None);
+    assert_eq!(v2.next().target, "/admin.cgi?sid=2&pass=secret&mode=updinfo&song=Someone%20-%20Rise");
+}
+
+#[test]
+fn failed_updates_are_retried() {
+    let icecast = StandIn::start(vec![500, 401, 500, 500]);
+    let relay = Relay::start_with(&settings(&format!(r#"
+        [[outputs.streams]]
+        server = "icecast"
+        url = "{}"
+        mount = "/live"
+        retries = 2
+        retry_delay = 50
+        "#, icecast.url)));
+
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    for _ in 0..3 {
+        assert!(icecast.next().target.ends_with("&song=Someone%20-%20Rise"));
+    }
+    // Gave up after the retries
+    assert!(icecast.nothing_more());
+
+    // The next song is tried again from the start
+    relay.traktor("/deckLoaded/B", track("Fall", "Someone Else"));
+    relay.traktor("/updateDeck/B", json!({ "isPlaying": true }));
+    assert!(icecast.next().target.ends_with("&song=Someone%20Else%20-%20Fall"));
+    assert!(icecast.next().target.ends_with("&song=Someone%20Else%20-%20Fall"));
+    assert!(icecast.nothing_more());
+}
+
+#[test]
+fn a_dead_server_does_not_hold_up_the_others() {
+    // Takes the connection but never answers
+    let dead = TcpListener::bind("127.0.0.1:0").unwrap();
+    let icecast = StandIn::start(vec![]);
+    let relay = Relay::start_with(&settings(&format!(r#"
+        [[outputs.streams]]
+        server = "shoutcast1"
+        url = "http://{}"
+
+        [[outputs.streams]]
+        server = "icecast"
+        url = "{}"
+        mount = "/live"
+        "#, dead.local_addr().unwrap(), icecast.url)));
+
+    let started = std::time::Instant::now();
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    assert!(icecast.next().target.ends_with("&song=Someone%20-%20Rise"));
+    assert!(started.elapsed() < Duration::from_secs(2), "{:?}", started.elapsed());
+}
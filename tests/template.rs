@@ -0,0 +1,41 @@
+//! Placeholders in the output templates
+mod common;
+
+use traktor_obs_relay::{
+    api::{deck::DeckStatus, master_clock::MasterClock},
+    template,
+};
+
+fn values(track: Option<&DeckStatus>) -> template::TemplateValues {
+    let clock = MasterClock { deck: Some(String::from("A")), bpm: 127.996 };
+    template::values(track, &clock, 2)
+}
+
+#[test]
+fn placeholders_are_replaced() {
+    let mut track: DeckStatus = serde_json::from_value(common::track("Rise", "Someone")).unwrap();
+    track.deck = Some(String::from("A"));
+    track.elapsed_time = 61.2;
+    track.next_cue_pos = Some(90.0);
+    track.update_key_notation();
+    let values = values(Some(&track));
+
+    assert_eq!(template::render("%artist% — %title%", &values), "Someone — Rise");
+    assert_eq!(template::render("{artist} – {title} on {deck}", &values), "Someone – Rise on A");
+    assert_eq!(template::render("{bpm} BPM, {key} ({camelot})", &values), "128.00 BPM, Am (8A)");
+    assert_eq!(template::render("%elapsed% / %length%, cue in %nextCue%", &values), "1:01 / 5:00, cue in 0:29");
+}
+
+#[test]
+fn unknown_placeholders_are_kept() {
+    let values = values(None);
+    assert_eq!(template::render("100% {nothing} %mood%", &values), "100% {nothing} %mood%");
+    assert_eq!(template::render("[%artist%][%elapsed%] {bpm}", &values), "[][] 128.00");
+}
+
+#[test]
+fn used_placeholders_are_found() {
+    assert!(template::uses("%artist% at {bpm} BPM", &["bpm", "masterDeck"]));
+    assert!(template::uses("Deck %masterDeck%", &["bpm", "masterDeck"]));
+    assert!(!template::uses("%artist% — %title% (bpm)", &["bpm", "masterDeck"]));
+}
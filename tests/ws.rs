@@ -0,0 +1,313 @@
+//! Websocket events pushed to the widgets
+mod common;
+
+use common::{track, Relay};
+use serde_json::json;
+use tokio_tungstenite::tungstenite::{protocol::frame::coding::CloseCode, Message};
+
+#[test]
+fn deck_load_is_pushed() {
+    let relay = Relay::start();
+    let mut ws = relay.subscribe();
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+
+    let msg = ws.next();
+    assert_eq!(msg["tickedDeck"], json!(null));
+    assert_eq!(msg["songsOnAir"][0]["title"], "Rise");
+}
+
+#[test]
+fn deck_update_is_pushed_as_tick() {
+    let relay = Relay::start();
+    relay.traktor("/deckLoaded/B", track("Rise", "Someone"));
+    let mut ws = relay.subscribe();
+    relay.traktor("/updateDeck/B", json!({ "isPlaying": true }));
+
+    let msg = ws.next();
+    assert_eq!(msg["tickedDeck"], "B");
+    assert_eq!(msg["songsOnAir"][0]["isPlaying"], true);
+}
+
+#[test]
+fn master_clock_is_pushed() {
+    let relay = Relay::start();
+    let mut ws = relay.subscribe();
+    relay.traktor("/updateMasterClock", json!({ "deck": "B", "bpm": 130.0 }));
+
+    let msg = ws.next();
+    assert_eq!(msg, json!({ "bpm": 130.0, "masterDeck": "B", "decks": [] }));
+}
+
+#[test]
+fn channel_change_is_pushed() {
+    let relay = Relay::start();
+    relay.traktor("/deckLoaded/D", track("Rise", "Someone"));
+    let mut ws = relay.subscribe();
+    relay.traktor("/updateChannel/4", json!({ "isOnAir": false }));
+
+    let msg = ws.next();
+    assert_eq!(msg["songsOnAir"], json!([]));
+}
+
+#[test]
+fn every_subscriber_gets_events() {
+    let relay = Relay::start();
+    let mut first = relay.subscribe();
+    let mut second = relay.subscribe();
+    relay.traktor("/updateMasterClock", json!({ "deck": "A", "bpm": 120.0 }));
+
+    assert_eq!(first.next()["bpm"], 120.0);
+    assert_eq!(second.next()["bpm"], 120.0);
+}
+
+#[test]
+fn shutdown_closes_subscribers() {
+    let relay = Relay::start();
+    let mut ws = relay.subscribe();
+    let shutdown = relay.begin_shutdown();
+
+    let frame = ws.closed().expect("close frame is sent");
+    assert_eq!(frame.code, CloseCode::Away);
+    relay.runtime.block_on(shutdown).unwrap();
+    assert!(std::net::TcpStream::connect(relay.endpoints.http).is_err());
+    assert!(std::net::TcpStream::connect(relay.endpoints.ws).is_err());
+}
+
+#[test]
+fn traktor_going_silent_is_pushed() {
+    let relay = Relay::start_with(&common::settings_with(&[("mixing", "traktor_timeout", "1")]));
+    let mut ws = relay.subscribe();
+
+    relay.traktor("/updateMasterClock", json!({ "deck": "A", "bpm": 128.0 }));
+    let connected = ws.next_matching(|msg| msg["event"] == "traktorConnectionChanged");
+    assert_eq!(connected["traktorConnected"], true);
+
+    let silent = ws.next_matching(|msg| msg["event"] == "traktorConnectionChanged");
+    assert_eq!(silent["traktorConnected"], false);
+    assert!(silent["secondsSinceLastUpdate"].as_f64().unwrap() >= 1.0);
+}
+
+#[test]
+fn silent_subscribers_are_disconnected() {
+    let relay = Relay::start_with(&common::settings_with(&[("http", "ws_ping_interval", "1"), ("http", "ws_pong_timeout", "2")]));
+    let mut alive = relay.subscribe();
+    let _silent = relay.subscribe();
+    let peers = || relay.get("/status").json()["wsPeers"].as_u64().unwrap();
+    assert_eq!(peers(), 2);
+
+    // Reading makes the client answer the pings
+    let started = std::time::Instant::now();
+    while started.elapsed() < std::time::Duration::from_secs(4) {
+        match alive.next_frame() {
+            Message::Ping(_) | Message::Pong(_) => {}
+            other => panic!("unexpected frame {:?}", other),
+        }
+    }
+    assert_eq!(peers(), 1);
+}
+
+#[test]
+fn now_playing_can_be_requested() {
+    let relay = Relay::start();
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    let mut ws = relay.subscribe();
+
+    let resp = ws.command(json!({ "id": 7, "command": "getNowPlaying" }));
+    assert_eq!(resp["ok"], true);
+    assert_eq!(resp["result"]["songsOnAir"][0]["title"], "Rise");
+}
+
+#[test]
+fn templates_can_be_rendered() {
+    let settings = format!("{}\n[templates]\nchat = \"Now playing: %title%\"\n", common::TEST_SETTINGS);
+    let relay = Relay::start_with(&settings);
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    let mut ws = relay.subscribe();
+
+    let resp = ws.command(json!({ "id": 1, "command": "render", "name": "chat" }));
+    assert_eq!(resp["result"], "Now playing: Rise");
+    let resp = ws.command(json!({ "id": 2, "command": "render", "template": "{artist}" }));
+    assert_eq!(resp["result"], "Someone");
+    let resp = ws.command(json!({ "id": 3, "command": "render" }));
+    assert_eq!(resp["error"]["code"], "badRequest");
+}
+
+#[test]
+fn bad_commands_are_answered_with_errors() {
+    let relay = Relay::start();
+    let mut ws = relay.subscribe();
+
+    let resp = ws.command(json!({ "id": "a", "command": "selfDestruct" }));
+    assert_eq!(resp["ok"], false);
+    assert_eq!(resp["error"]["code"], "badRequest");
+
+    ws.send(json!("not even an object"));
+    let resp = ws.next_matching(|msg| msg["event"] == "response");
+    assert_eq!(resp["id"], json!(null));
+    assert_eq!(resp["ok"], false);
+}
+
+#[test]
+fn subscribers_only_get_chosen_topics() {
+    let relay = Relay::start();
+    let mut ws = relay.subscribe();
+    let resp = ws.command(json!({ "id": 1, "command": "subscribe", "events": ["bpm"] }));
+    assert_eq!(resp["ok"], true);
+
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    relay.traktor("/updateMasterClock", json!({ "deck": "A", "bpm": 128.0 }));
+    let msg = ws.next();
+    assert_eq!((&msg["bpm"], &msg["masterDeck"]), (&json!(128.0), &json!("A")));
+
+    let resp = ws.command(json!({ "id": 2, "command": "subscribe", "events": ["noSuchTopic"] }));
+    assert_eq!(resp["ok"], false);
+}
+
+#[test]
+fn history_lists_tracks_played_on_air() {
+    let relay = Relay::start();
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    relay.traktor("/deckLoaded/B", track("Fall", "Someone Else"));
+    relay.traktor("/updateChannel/2", json!({ "isOnAir": false }));
+    relay.traktor("/updateDeck/A", json!({ "isPlaying": true }));
+    relay.traktor("/updateDeck/B", json!({ "isPlaying": true }));
+    relay.traktor("/updateChannel/2", json!({ "isOnAir": true }));
+    relay.traktor("/updateDeck/A", json!({ "isPlaying": false }));
+    relay.traktor("/updateDeck/A", json!({ "isPlaying": true }));
+    let mut ws = relay.subscribe();
+
+    let resp = ws.command(json!({ "id": 1, "command": "getHistory" }));
+    let titles: Vec<&str> = resp["result"].as_array().unwrap().iter().map(|e| e["track"]["title"].as_str().unwrap()).collect();
+    assert_eq!(titles, vec!["Rise", "Fall"]);
+
+    let resp = ws.command(json!({ "id": 2, "command": "getHistory", "limit": 1 }));
+    assert_eq!(resp["result"][0]["deck"], "B");
+    assert_eq!(relay.get("/history").json().as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn slow_commands_do_not_hold_up_the_connection() {
+    let relay = Relay::start_with(&common::settings_with(&[("http", "ws_ping_interval", "1"), ("http", "ws_pong_timeout", "2")]));
+    let mut ws = relay.subscribe();
+
+    // The command waits for the state for longer than the pong timeout
+    let state = relay.app.state_mut().unwrap();
+    ws.send(json!({ "id": 1, "command": "getNowPlaying" }));
+    let mut pings = 0;
+    let started = std::time::Instant::now();
+    while started.elapsed() < std::time::Duration::from_secs(3) {
+        match ws.next_frame() {
+            Message::Ping(_) => pings += 1,
+            other => panic!("unexpected frame {:?}", other),
+        }
+    }
+    drop(state);
+
+    let reply = ws.next_matching(|msg| msg["event"] == "response" && msg["id"] == 1);
+    assert_eq!(reply["ok"], true);
+    assert!(pings >= 2, "{} pings", pings);
+}
+
+#[test]
+fn override_command_is_broadcast() {
+    let relay = Relay::start();
+    relay.traktor("/deckLoaded/A", track("Track 03", "Unknown Artist"));
+    let mut panel = relay.subscribe();
+    let mut widget = relay.subscribe();
+
+    let resp = panel.command(json!({ "id": 1, "command": "setOverride", "deck": "A", "title": "Real Title" }));
+    assert_eq!(resp["ok"], true, "{}", resp);
+    assert_eq!(resp["result"]["deck"]["title"], "Real Title");
+
+    let event = widget.next();
+    assert_eq!(event["event"], "overrideChanged");
+    assert_eq!(event["deck"], "A");
+    assert_eq!(widget.next()["songsOnAir"][0]["title"], "Real Title");
+
+    let resp = panel.command(json!({ "id": 2, "command": "getOverride", "deck": "A" }));
+    assert_eq!(resp["result"]["deck"]["title"], "Real Title");
+
+    let resp = panel.command(json!({ "id": 3, "command": "setOverride", "deck": "A", "titel": "Typo" }));
+    assert_eq!(resp["error"]["code"], "badRequest");
+    let resp = panel.command(json!({ "id": 4, "command": "setOverride", "deck": "A", "clear": true }));
+    assert_eq!(resp["result"]["deck"], json!(null));
+}
+
+#[test]
+fn mix_hints_follow_tempo_changes() {
+    let relay = Relay::start();
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    relay.traktor("/deckLoaded/B", track("Fall", "Someone"));
+    let mut ws = relay.subscribe();
+    ws.command(json!({ "id": 1, "command": "subscribe", "events": ["mixHints"] }));
+
+    relay.traktor("/updateDeck/B", json!({ "tempo": 1.02, "resultingKey": "2m" }));
+    let event = ws.next();
+    assert_eq!(event["event"], "mixHints");
+    assert_eq!(event["hints"][0]["keyRelation"], "adjacent");
+    assert!(event["hints"][0]["bpmDifference"].as_f64().unwrap() > 2.5);
+
+    let resp = ws.command(json!({ "id": 2, "command": "getMixHints" }));
+    assert_eq!(resp["result"][0]["keyRelation"], "adjacent");
+}
+
+#[test]
+fn bpm_message_lists_deck_tempos() {
+    let relay = Relay::start();
+    relay.traktor("/deckLoaded/A", track("Rise", "Someone"));
+    let mut ws = relay.subscribe();
+    relay.traktor("/updateMasterClock", json!({ "deck": "A", "bpm": 128.05 }));
+    let msg = ws.next_matching(|msg| msg.get("masterDeck").is_some());
+    assert_eq!(
+        msg["decks"],
+        json!([{ "deck": "A", "effectiveBpm": 128.0, "isMaster": true, "isSynced": true, "matchesMaster": true }])
+    );
+}
+
+#[test]
+fn bpm_jitter_is_smoothed() {
+    let relay = Relay::start_with(&common::settings_with(&[
+        ("http", "bpm_precision", "1"),
+        ("http", "bpm_min_delta", "0.1"),
+        ("http", "bpm_rate_limit", "200"),
+        ("http", "bpm_settle_time", "300"),
+    ]));
+    let mut ws = relay.subscribe();
+    ws.command(json!({ "id": 1, "command": "subscribe", "events": ["bpm", "bpmSettled"] }));
+
+    relay.traktor("/updateMasterClock", json!({ "deck": "A", "bpm": 128.0 }));
+    relay.traktor("/updateMasterClock", json!({ "deck": "A", "bpm": 128.04 }));
+    relay.traktor("/updateMasterClock", json!({ "deck": "A", "bpm": 127.98 }));
+    relay.traktor("/updateMasterClock", json!({ "deck": "A", "bpm": 129.37 }));
+
+    assert_eq!(ws.next()["bpm"], 128.0);
+    // Last change waits for the rate limit, and comes rounded
+    let msg = ws.next();
+    assert!(msg.get("event").is_none(), "{}", msg);
+    assert_eq!(msg["bpm"], 129.4);
+    let settled = ws.next();
+    assert_eq!(settled["event"], "bpmSettled");
+    assert_eq!(settled["bpm"], 129.4);
+    assert_eq!(settled["masterDeck"], "A");
+
+    // Polling still gets the exact value
+    let bpm = relay.get("/nowPlaying").json()["bpm"].as_f64().unwrap();
+    assert!((bpm - 129.37).abs() < 0.001, "{}", bpm);
+}
+
+#[test]
+fn bpm_returning_to_the_sent_value_settles() {
+    let relay = Relay::start_with(&common::settings_with(&[("http", "bpm_rate_limit", "200"), ("http", "bpm_settle_time", "300")]));
+    let mut ws = relay.subscribe();
+    ws.command(json!({ "id": 1, "command": "subscribe", "events": ["bpm", "bpmSettled"] }));
+
+    relay.traktor("/updateMasterClock", json!({ "deck": "A", "bpm": 128.0 }));
+    relay.traktor("/updateMasterClock", json!({ "deck": "A", "bpm": 128.05 }));
+    relay.traktor("/updateMasterClock", json!({ "deck": "A", "bpm": 128.0 }));
+
+    assert_eq!(ws.next()["bpm"], 128.0);
+    // The waiting change is dropped rather than retried forever
+    let settled = ws.next();
+    assert_eq!(settled["event"], "bpmSettled");
+    assert_eq!(settled["bpm"], 128.0);
+}